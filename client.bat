@@ -1,7 +1,29 @@
 wscat -c ws://84.30.14.3:25656
 
+:: hello        {"type":"hello","protocols":[1],"features":["chat","file","p2p","resume"]}
 :: login User A {"type":"login","name":"User A"}
 :: login User B {"type":"login","name":"User B"}
+:: register     {"type":"login","name":"User C","password":"secret"}
+:: bot login    {"type":"login","api_token":""}
+:: resume       {"type":"resume","token":""}
+:: chat         {"type":"chat","content":"","nonce":"1"}
+:: edit         {"type":"edit","id":0,"content":""}
+:: delete       {"type":"delete","id":0}
+:: direct       {"type":"chat","content":"","target":""}
+:: read         {"type":"read","id":0}
+:: reply        {"type":"chat","content":"","parent":0}
+:: react        {"type":"react","id":0,"emoji":"👍"}
+:: history      {"type":"history","limit":50}
+:: presence     {"type":"presence","status":"away","text":""}
+:: typing       {"type":"typing","typing":true}
 :: request      {"type":"request","target":""}
 :: accept       {"type":"offer","accept":true,"id":""}
-:: decline      {"type":"offer","accept":false,"id":""}
\ No newline at end of file
+:: decline      {"type":"offer","accept":false,"id":""}
+:: block        {"type":"block","target":""}
+:: block chat   {"type":"block","target":"","chat":true}
+:: unblock      {"type":"block","target":"","remove":true}
+:: rename       {"type":"rename","name":""}
+:: profile      {"type":"profile","id":""}
+:: edit profile {"type":"profile","update":{"display_name":"","bio":"","status":"","timezone":""}}
+:: avatar       {"type":"file","name":"avatar.png","content":"","avatar":true}
+:: command      {"type":"chat","content":"/help"}
\ No newline at end of file
@@ -0,0 +1,273 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use flow_client::{
+    base64,
+    protocol::{Presence, Topic, User},
+    Me, OnlineUser, ServerMessage,
+};
+
+/**
+ * Most lines kept in the chat pane.
+ */
+pub const MAX_LINES: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Chat,
+    Direct,
+    Own,
+    Info,
+    Error,
+}
+
+/**
+ * A line in the chat pane, chat lines keep their id so edits and deletes can find them.
+ */
+pub struct Line {
+    pub id: Option<u64>,
+    pub kind: Kind,
+    pub text: String,
+}
+
+/**
+ * A p2p offer waiting for the user to accept or decline it.
+ */
+pub struct Offer {
+    pub id: String,
+    pub origin: String,
+}
+
+/**
+ * What the user wants done after pressing enter.
+ */
+pub enum Input {
+    Chat(String),
+    File(PathBuf),
+    Avatar(PathBuf),
+    Profile(String),
+    Request(String),
+    Quit,
+}
+
+/**
+ * Everything the terminal client shows.
+ */
+pub struct App {
+    pub me: Option<Me>,
+    pub users: Vec<OnlineUser>,
+    pub lines: Vec<Line>,
+    pub input: String,
+    pub offers: VecDeque<Offer>,
+    pub downloads: PathBuf,
+}
+
+impl App {
+    pub fn new(me: Option<Me>, users: Vec<OnlineUser>, downloads: PathBuf) -> App {
+        App { me, users, lines: Vec::new(), input: String::new(), offers: VecDeque::new(), downloads }
+    }
+
+    pub fn push(&mut self, id: Option<u64>, kind: Kind, text: String) {
+        self.lines.push(Line { id, kind, text });
+        if self.lines.len() > MAX_LINES {
+            self.lines.remove(0);
+        }
+    }
+
+    pub fn info(&mut self, text: String) {
+        self.push(None, Kind::Info, text);
+    }
+
+    pub fn error(&mut self, text: String) {
+        self.push(None, Kind::Error, text);
+    }
+
+    /**
+     * Get the name of an online user, or the id if they aren't known.
+     */
+    pub fn name_of(&self, id: &str) -> String {
+        self.users.iter()
+            .find(|user| user.id == id)
+            .map(|user| user.name.clone())
+            .unwrap_or_else(|| id.to_string())
+    }
+
+    fn find_line(&mut self, id: u64) -> Option<&mut Line> {
+        self.lines.iter_mut().find(|line| line.id == Some(id))
+    }
+
+    /**
+     * Update the screen for a message from the server.
+     */
+    pub fn receive(&mut self, msg: ServerMessage) {
+        match msg {
+            ServerMessage::Join { user } => {
+                self.info(format!("{} joined", user.name));
+                self.users.retain(|online| online.id != user.id);
+                self.users.push(online(user));
+            }
+            ServerMessage::Leave { user } => {
+                self.info(format!("{} left", user.name));
+                self.users.retain(|online| online.id != user.id);
+                self.offers.retain(|offer| offer.origin != user.id);
+            }
+            ServerMessage::Chat(chat) => {
+                let Some(content) = chat.content else { return };
+                if chat.action {
+                    self.push(Some(chat.id), Kind::Chat, format!("* {} {}", chat.sender.name, content));
+                    return;
+                }
+                match chat.target {
+                    Some(_) => self.push(Some(chat.id), Kind::Direct, format!("{} (direct): {}", chat.sender.name, content)),
+                    None => self.push(Some(chat.id), Kind::Chat, format!("{}: {}", chat.sender.name, content)),
+                }
+            }
+            ServerMessage::Edit { id, content, .. } => {
+                if let Some(line) = self.find_line(id) {
+                    let name = line.text.split_once(": ").map(|(name, _)| name.to_string()).unwrap_or_default();
+                    line.text = format!("{}: {} (edited)", name, content);
+                }
+            }
+            ServerMessage::Delete { id, .. } => {
+                if let Some(line) = self.find_line(id) {
+                    line.kind = Kind::Info;
+                    line.text = String::from("(deleted)");
+                }
+            }
+            ServerMessage::File(file) => {
+                let saved = base64::decode(&file.content)
+                    .ok_or_else(|| String::from("invalid content"))
+                    .and_then(|content| self.save(file.id, &file.name, &content));
+
+                match saved {
+                    Ok(path) => self.info(format!("{} shared {}, saved to {}", file.sender.name, file.name, path.display())),
+                    Err(err) => self.error(format!("{} shared {}, couldn't save it ({})", file.sender.name, file.name, err)),
+                }
+            }
+            ServerMessage::Offer { origin, id } => {
+                self.info(format!("{} wants to connect, Ctrl+Y to accept or Ctrl+N to decline", self.name_of(&origin)));
+                self.offers.push_back(Offer { id, origin });
+            }
+            ServerMessage::Confirm { accept, offer } => {
+                self.offers.retain(|pending| pending.id != offer);
+                match accept {
+                    true => self.info(String::from("Connection accepted")),
+                    false => self.info(String::from("Connection declined")),
+                }
+            }
+            ServerMessage::Peer { addr, .. } => self.info(format!("Peer is listening at {}", addr)),
+            ServerMessage::Rename { user, old } => {
+                self.info(format!("{} is now known as {}", old, user.name));
+                match &mut self.me {
+                    Some(me) if me.id == user.id => me.name = user.name,
+                    _ => {
+                        self.users.retain(|online| online.id != user.id);
+                        self.users.push(online(user));
+                    }
+                }
+            }
+            ServerMessage::Topic(topic) => self.topic(topic),
+            ServerMessage::Profile { name, profile, .. } => {
+                let fields = [
+                    ("Display name", profile.display_name),
+                    ("Avatar", profile.avatar.map(|avatar| avatar.name)),
+                    ("Bio", profile.bio),
+                    ("Status", profile.status),
+                    ("Timezone", profile.timezone),
+                ];
+
+                self.info(format!("Profile of {}", name));
+                for (field, value) in fields {
+                    if let Some(value) = value {
+                        self.info(format!("  {}: {}", field, value));
+                    }
+                }
+            }
+            ServerMessage::Command { content, .. } => {
+                for line in content.lines() {
+                    self.info(line.to_string());
+                }
+            }
+            ServerMessage::Announcement { content } => self.info(format!("Announcement: {}", content)),
+            ServerMessage::Role { role } => self.info(format!("Your role is now {}", role)),
+            ServerMessage::Shutdown { .. } => self.info(String::from("Server is shutting down")),
+            ServerMessage::Error { source, message } => self.error(format!("{}: {}", source, message)),
+            ServerMessage::Invalid { error, .. } => self.error(format!("Couldn't read a message from the server ({})", error)),
+            _ => {}
+        }
+    }
+
+    /**
+     * Show the topic when it changes.
+     */
+    pub fn topic(&mut self, topic: Topic) {
+        match (topic.topic, topic.by) {
+            (Some(topic), Some(by)) => self.info(format!("Topic: {} (set by {})", topic, by)),
+            _ => self.info(String::from("There is no topic")),
+        }
+    }
+
+    /**
+     * Save a received file in the downloads directory, files with the same name get the message id in front.
+     */
+    fn save(&self, id: u64, name: &str, content: &[u8]) -> Result<PathBuf, String> {
+        let name = Path::new(name).file_name().ok_or_else(|| String::from("invalid name"))?;
+        fs::create_dir_all(&self.downloads).map_err(|err| err.to_string())?;
+
+        let mut path = self.downloads.join(name);
+        if path.exists() {
+            path = self.downloads.join(format!("{}-{}", id, name.to_string_lossy()));
+        }
+
+        fs::write(&path, content).map_err(|err| err.to_string())?;
+        Ok(path)
+    }
+
+    /**
+     * Take what the user typed, a few commands are handled locally,
+     * everything else goes to the server as chat, including its slash commands.
+     */
+    pub fn submit(&mut self) -> Option<Input> {
+        let input = std::mem::take(&mut self.input);
+        let input = input.trim();
+        if input.is_empty() {
+            return None;
+        }
+
+        let (command, rest) = input.split_once(' ').unwrap_or((input, ""));
+        let rest = rest.trim();
+        match command {
+            "/quit" => Some(Input::Quit),
+            "/file" if !rest.is_empty() => Some(Input::File(PathBuf::from(rest))),
+            "/avatar" if !rest.is_empty() => Some(Input::Avatar(PathBuf::from(rest))),
+            "/request" | "/profile" if !rest.is_empty() => {
+                let me = self.me.as_ref().filter(|me| me.name == rest || me.id == rest).map(|me| me.id.clone());
+                let target = me.or_else(|| self.users.iter().find(|user| user.name == rest || user.id == rest).map(|user| user.id.clone()));
+                match (target, command) {
+                    (Some(target), "/request") => Some(Input::Request(target)),
+                    (Some(target), _) => Some(Input::Profile(target)),
+                    (None, _) => {
+                        self.error(format!("{} isn't online", rest));
+                        None
+                    }
+                }
+            }
+            "/file" | "/avatar" => {
+                self.error(format!("Usage: {} <path>", command));
+                None
+            }
+            "/request" | "/profile" => {
+                self.error(format!("Usage: {} <name>", command));
+                None
+            }
+            _ => Some(Input::Chat(input.to_string())),
+        }
+    }
+}
+
+fn online(user: User) -> OnlineUser {
+    OnlineUser { id: user.id, name: user.name, bot: user.bot, presence: Presence { status: String::from("online"), text: None } }
+}
@@ -0,0 +1,151 @@
+use std::{env, fs, path::PathBuf, thread};
+
+use flow_client::{Client, Error, Sender};
+use futures_util::StreamExt;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use tokio::sync::mpsc;
+
+mod app;
+mod ui;
+
+use app::{App, Input, Kind};
+
+/**
+ * A terminal client for flow.
+ * flow-cli <url> <name> [password]
+ * Files others share are saved in FLOW_DOWNLOADS (default "downloads").
+ */
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        eprintln!("Usage: flow-cli <url> <name> [password]");
+        std::process::exit(1);
+    }
+
+    let mut client = Client::connect(&args[1]).await?;
+    let users = client.login(&args[2], args.get(3).map(String::as_str)).await?;
+    let downloads = PathBuf::from(env::var("FLOW_DOWNLOADS").unwrap_or_else(|_| String::from("downloads")));
+    let mut app = App::new(client.me.clone(), users, downloads);
+
+    let (sender, mut events) = client.split();
+
+    // Reading the terminal blocks, so it gets its own thread:
+    let (keys_tx, mut keys) = mpsc::channel(64);
+    thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            if keys_tx.blocking_send(event).is_err() {
+                return;
+            }
+        }
+    });
+
+    let mut terminal = ratatui::init();
+    loop {
+        if terminal.draw(|frame| ui::draw(frame, &app)).is_err() {
+            break;
+        }
+
+        tokio::select! {
+            msg = events.next() => match msg {
+                Some(msg) => app.receive(msg),
+                None => break,
+            },
+            Some(event) = keys.recv() => {
+                let Event::Key(key) = event else { continue };
+                if !key_pressed(&mut app, &sender, key).await {
+                    break;
+                }
+            }
+        }
+    }
+    ratatui::restore();
+
+    // The connection may already be gone:
+    sender.close().await.ok();
+    Ok(())
+}
+
+/**
+ * Handle a key press, returns false once the user wants to quit.
+ */
+async fn key_pressed(app: &mut App, sender: &Sender, key: KeyEvent) -> bool {
+    if key.kind != KeyEventKind::Press {
+        return true;
+    }
+
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    let result = match key.code {
+        KeyCode::Esc => return false,
+        KeyCode::Char('c') if ctrl => return false,
+        KeyCode::Char('y') if ctrl => match app.offers.pop_front() {
+            Some(offer) => sender.accept(&offer.id).await,
+            None => Ok(()),
+        },
+        KeyCode::Char('n') if ctrl => match app.offers.pop_front() {
+            Some(offer) => sender.decline(&offer.id).await,
+            None => Ok(()),
+        },
+        KeyCode::Char(c) if !ctrl => {
+            app.input.push(c);
+            Ok(())
+        }
+        KeyCode::Backspace => {
+            app.input.pop();
+            Ok(())
+        }
+        KeyCode::Enter => match app.submit() {
+            Some(Input::Quit) => return false,
+            Some(input) => submit(app, sender, input).await,
+            None => Ok(()),
+        },
+        _ => Ok(()),
+    };
+
+    if let Err(err) = result {
+        app.error(err.to_string());
+    }
+    true
+}
+
+/**
+ * Send what the user typed to the server.
+ */
+async fn submit(app: &mut App, sender: &Sender, input: Input) -> Result<(), Error> {
+    match input {
+        Input::Chat(content) => {
+            sender.chat(&content).await?;
+
+            // Commands are answered by the server, two slashes send one:
+            let content = match content.strip_prefix('/') {
+                Some(rest) if rest.starts_with('/') => rest.to_string(),
+                Some(_) => return Ok(()),
+                None => content,
+            };
+            let name = app.me.as_ref().map(|me| me.name.clone()).unwrap_or_default();
+            app.push(None, Kind::Own, format!("{}: {}", name, content));
+        }
+        Input::File(path) => match fs::read(&path) {
+            Ok(content) => {
+                let name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+                sender.send_file(&name, &content).await?;
+                app.info(format!("Shared {}", name));
+            }
+            Err(err) => app.error(format!("Couldn't read {} ({})", path.display(), err)),
+        },
+        Input::Avatar(path) => match fs::read(&path) {
+            Ok(content) => {
+                let name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+                sender.set_avatar(&name, &content).await?;
+            }
+            Err(err) => app.error(format!("Couldn't read {} ({})", path.display(), err)),
+        },
+        Input::Profile(id) => sender.profile(&id).await?,
+        Input::Request(target) => {
+            sender.request(&target).await?;
+            app.info(format!("Asked {} to connect", app.name_of(&target)));
+        }
+        Input::Quit => {}
+    }
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use ratatui::{
+    layout::{Constraint, Layout, Position},
+    style::{Color, Style},
+    text::Line,
+    widgets::{Block, List, ListItem, Paragraph},
+    Frame,
+};
+
+use crate::app::{App, Kind};
+
+/**
+ * Draw the chat pane, the online users and the input box.
+ */
+pub fn draw(frame: &mut Frame, app: &App) {
+    let [main, input] = Layout::vertical([Constraint::Min(3), Constraint::Length(3)]).areas(frame.area());
+    let [chat, users] = Layout::horizontal([Constraint::Min(20), Constraint::Length(24)]).areas(main);
+
+    // Only the newest lines that fit are shown:
+    let height = chat.height.saturating_sub(2) as usize;
+    let lines: Vec<Line> = app.lines[app.lines.len().saturating_sub(height)..]
+        .iter()
+        .map(|line| Line::styled(line.text.as_str(), style(line.kind)))
+        .collect();
+
+    let title = match &app.me {
+        Some(me) => format!(" flow - {} ", me.name),
+        None => String::from(" flow "),
+    };
+    frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(title)), chat);
+
+    let online: Vec<ListItem> = app.users
+        .iter()
+        .map(|user| match user.bot {
+            true => ListItem::new(format!("{} [bot]", user.name)).style(Style::new().fg(Color::Cyan)),
+            false => ListItem::new(user.name.as_str()),
+        })
+        .collect();
+    frame.render_widget(List::new(online).block(Block::bordered().title(format!(" Online ({}) ", app.users.len()))), users);
+
+    // A pending offer takes over the title of the input box:
+    let block = match app.offers.front() {
+        Some(offer) => Block::bordered()
+            .title(format!(" {} wants to connect: Ctrl+Y accept, Ctrl+N decline ", app.name_of(&offer.origin)))
+            .border_style(Style::new().fg(Color::Yellow)),
+        None => Block::bordered().title(" Message (/help, /file <path>, /request <name>, /quit) "),
+    };
+    frame.render_widget(Paragraph::new(app.input.as_str()).block(block), input);
+    frame.set_cursor_position(Position::new(input.x + 1 + app.input.chars().count() as u16, input.y + 1));
+}
+
+fn style(kind: Kind) -> Style {
+    match kind {
+        Kind::Chat => Style::new(),
+        Kind::Direct => Style::new().fg(Color::Magenta),
+        Kind::Own => Style::new().fg(Color::Green),
+        Kind::Info => Style::new().fg(Color::DarkGray),
+        Kind::Error => Style::new().fg(Color::Red),
+    }
+}
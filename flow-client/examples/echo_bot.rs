@@ -0,0 +1,25 @@
+use std::env;
+
+use flow_client::{bot::Bot, Client, Error};
+
+/**
+ * A bot that repeats what you tell it, get a token with the admin command `bot <name>`.
+ * FLOW_URL=ws://127.0.0.1:25656 FLOW_BOT_TOKEN=... cargo run -p flow-client --example echo_bot
+ */
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let url = env::var("FLOW_URL").unwrap_or_else(|_| "ws://127.0.0.1:25656".to_string());
+    let token = env::var("FLOW_BOT_TOKEN").expect("FLOW_BOT_TOKEN must be set");
+
+    let mut client = Client::connect(&url).await?;
+    client.login_bot(&token).await?;
+    if let Some(me) = &client.me {
+        println!("Logged in as {}", me.name);
+    }
+
+    let bot = Bot::new("!")
+        .command("echo", "Repeat a message", |command| Some(command.rest.clone()))
+        .command("shout", "Repeat a message, louder", |command| Some(command.rest.to_uppercase()));
+
+    bot.run(&mut client).await
+}
@@ -0,0 +1,91 @@
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/**
+ * Encode bytes as standard base64 with padding, the way files are send.
+ */
+pub fn encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+
+    out
+}
+
+/**
+ * Decode standard base64, the padding is optional. None if it isn't valid.
+ */
+pub fn decode(text: &str) -> Option<Vec<u8>> {
+    // Padding only fills up the last group of four, and one character can't make a byte:
+    let trimmed = text.trim_end_matches('=');
+    let padding = text.len() - trimmed.len();
+    if padding > 2 || trimmed.len() % 4 == 1 || (padding > 0 && !text.len().is_multiple_of(4)) {
+        return None;
+    }
+
+    let text = trimmed;
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+
+    let mut n: u32 = 0;
+    let mut bits = 0;
+    for c in text.bytes() {
+        n = n << 6 | ALPHABET.iter().position(|a| *a == c)? as u32;
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            out.push((n >> bits) as u8);
+        }
+    }
+
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode};
+
+    #[test]
+    fn round_trip() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        for len in 0..16 {
+            assert_eq!(decode(&encode(&bytes[..len])).as_deref(), Some(&bytes[..len]), "length {}", len);
+        }
+        assert_eq!(decode(&encode(&bytes)), Some(bytes));
+    }
+
+    #[test]
+    fn known_values() {
+        for (bytes, text) in [("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v"), ("foob", "Zm9vYg=="), ("hello", "aGVsbG8=")] {
+            assert_eq!(encode(bytes.as_bytes()), text);
+            assert_eq!(decode(text).as_deref(), Some(bytes.as_bytes()));
+        }
+        assert_eq!(encode(&[0xfb, 0xff, 0xbf]), "+/+/");
+    }
+
+    #[test]
+    fn without_padding() {
+        assert_eq!(decode("Zg").as_deref(), Some(&b"f"[..]));
+        assert_eq!(decode("Zm8").as_deref(), Some(&b"fo"[..]));
+    }
+
+    #[test]
+    fn bad_input() {
+        assert_eq!(decode("Zm9v!"), None);
+        assert_eq!(decode("Zm 9v"), None);
+        assert_eq!(decode("Zm=v"), None);
+        assert_eq!(decode("Z"), None);
+        assert_eq!(decode("Zm9vY"), None);
+        assert_eq!(decode("Zg==="), None);
+        assert_eq!(decode("Zg="), None);
+        assert_eq!(decode("Zm9v-_"), None);
+    }
+}
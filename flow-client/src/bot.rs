@@ -0,0 +1,122 @@
+use crate::{protocol::Chat, Client, Error, Sender, ServerMessage};
+
+/**
+ * A command someone send to the bot, e.g. "!echo hello world".
+ */
+#[derive(Debug, Clone)]
+pub struct Command {
+    pub name: String,
+    pub args: Vec<String>,
+    pub rest: String,
+    pub sender: String,
+    pub message: u64,
+    pub direct: bool,
+}
+
+type Handler = Box<dyn Fn(&Command) -> Option<String> + Send + Sync>;
+
+/**
+ * A chat bot that answers commands, replies go back the way the command came:
+ * direct messages get a direct message and public ones a reply in their thread.
+ */
+pub struct Bot {
+    prefix: String,
+    commands: Vec<(String, String, Handler)>,
+}
+
+impl Bot {
+    /**
+     * A bot listening to commands starting with a prefix, e.g. "!".
+     * It knows the help command out of the box.
+     */
+    pub fn new(prefix: &str) -> Bot {
+        Bot { prefix: prefix.to_string(), commands: Vec::new() }
+    }
+
+    /**
+     * Answer a command with the reply the handler returns, if any.
+     */
+    pub fn command(
+        mut self,
+        name: &str,
+        description: &str,
+        handler: impl Fn(&Command) -> Option<String> + Send + Sync + 'static,
+    ) -> Bot {
+        self.commands.push((name.to_string(), description.to_string(), Box::new(handler)));
+        self
+    }
+
+    /**
+     * Get the command from a chat message, if it is one.
+     */
+    pub fn parse(&self, chat: &Chat) -> Option<Command> {
+        let content = chat.content.as_deref()?.strip_prefix(&self.prefix)?;
+        let (name, rest) = content.split_once(' ').unwrap_or((content, ""));
+        if name.is_empty() {
+            return None;
+        }
+
+        Some(Command {
+            name: name.to_lowercase(),
+            args: rest.split_whitespace().map(String::from).collect(),
+            rest: rest.trim().to_string(),
+            sender: chat.sender.id.clone(),
+            message: chat.id,
+            direct: chat.target.is_some(),
+        })
+    }
+
+    /**
+     * List the commands of the bot.
+     */
+    pub fn help(&self) -> String {
+        let mut help = format!("{}help - List the commands", self.prefix);
+        for (name, description, _) in self.commands.iter() {
+            help += &format!("\n{}{} - {}", self.prefix, name, description);
+        }
+        help
+    }
+
+    /**
+     * Answer a command, None if the bot doesn't know it.
+     */
+    pub fn answer(&self, command: &Command) -> Option<String> {
+        if command.name == "help" {
+            return Some(self.help());
+        }
+
+        let (_, _, handler) = self.commands.iter().find(|(name, _, _)| *name == command.name)?;
+        handler(command)
+    }
+
+    /**
+     * Answer commands until the connection closes.
+     */
+    pub async fn run(&self, client: &mut Client) -> Result<(), Error> {
+        let me = client.me.as_ref().map(|me| me.id.clone()).unwrap_or_default();
+
+        while let Some(msg) = client.next().await {
+            let ServerMessage::Chat(chat) = msg else { continue };
+            if chat.sender.id == me {
+                continue;
+            }
+
+            let Some(command) = self.parse(&chat) else { continue };
+            if let Some(answer) = self.answer(&command) {
+                reply(client, &command, &answer).await?;
+            }
+        }
+
+        Err(Error::Closed)
+    }
+}
+
+/**
+ * Reply to a command the way it came in.
+ */
+pub async fn reply(sender: &Sender, command: &Command, content: &str) -> Result<(), Error> {
+    match command.direct {
+        true => sender.direct(&command.sender, content).await,
+        false => sender.reply(command.message, content).await,
+    }
+}
@@ -0,0 +1,266 @@
+use std::{fmt, ops::Deref, sync::Arc};
+
+use futures_util::{
+    stream::{BoxStream, SplitSink},
+    SinkExt, StreamExt,
+};
+use tokio::{net::TcpStream, sync::Mutex};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+pub use flow_protocol::{self as protocol, ClientMessage, Me, OnlineUser, ProfileUpdate, ServerMessage};
+
+pub mod base64;
+pub mod bot;
+
+type Stream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/**
+ * The messages from the server, in the order they arrived.
+ * Messages that can't be decoded come through as `ServerMessage::Invalid`.
+ */
+pub type Events = BoxStream<'static, ServerMessage>;
+
+/**
+ * Things that can go wrong while talking to a flow server.
+ */
+#[derive(Debug)]
+pub enum Error {
+    Socket(tokio_tungstenite::tungstenite::Error),
+    Closed,
+    Server(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Socket(err) => write!(f, "Socket error ({})", err),
+            Error::Closed => f.write_str("Connection closed"),
+            Error::Server(err) => write!(f, "Server error ({})", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(err: tokio_tungstenite::tungstenite::Error) -> Error {
+        Error::Socket(err)
+    }
+}
+
+/**
+ * Sends messages to the server, clones share the same connection.
+ */
+#[derive(Clone)]
+pub struct Sender {
+    write: Arc<Mutex<SplitSink<Stream, Message>>>,
+}
+
+impl Sender {
+    /**
+     * Send a message to the server.
+     */
+    pub async fn send(&self, msg: ClientMessage) -> Result<(), Error> {
+        let text = serde_json::to_string(&msg).expect("Client messages can be serialized");
+        self.write.lock().await.send(Message::Text(text.into())).await?;
+        Ok(())
+    }
+
+    /**
+     * Send a chat message to everyone.
+     */
+    pub async fn chat(&self, content: &str) -> Result<(), Error> {
+        self.send(ClientMessage::Chat { content: content.to_string(), target: None, parent: None, nonce: None }).await
+    }
+
+    /**
+     * Send a direct message to one user.
+     */
+    pub async fn direct(&self, target: &str, content: &str) -> Result<(), Error> {
+        self.send(ClientMessage::Chat { content: content.to_string(), target: Some(target.to_string()), parent: None, nonce: None }).await
+    }
+
+    /**
+     * Reply to a chat message in its thread.
+     */
+    pub async fn reply(&self, parent: u64, content: &str) -> Result<(), Error> {
+        self.send(ClientMessage::Chat { content: content.to_string(), target: None, parent: Some(parent), nonce: None }).await
+    }
+
+    /**
+     * Change your name, everyone gets a rename with the old and new name.
+     */
+    pub async fn rename(&self, name: &str) -> Result<(), Error> {
+        self.send(ClientMessage::Rename { name: name.to_string() }).await
+    }
+
+    /**
+     * Share a file with everyone.
+     */
+    pub async fn send_file(&self, name: &str, content: &[u8]) -> Result<(), Error> {
+        self.send(ClientMessage::File { name: name.to_string(), content: base64::encode(content), nonce: None, avatar: false }).await
+    }
+
+    /**
+     * Change your avatar, it has to be a small image.
+     */
+    pub async fn set_avatar(&self, name: &str, content: &[u8]) -> Result<(), Error> {
+        self.send(ClientMessage::File { name: name.to_string(), content: base64::encode(content), nonce: None, avatar: true }).await
+    }
+
+    /**
+     * Ask for the profile of a user, it comes back as a profile message.
+     */
+    pub async fn profile(&self, id: &str) -> Result<(), Error> {
+        self.send(ClientMessage::Profile { id: Some(id.to_string()), update: None }).await
+    }
+
+    /**
+     * Change your own profile, everyone gets the new one without the avatar content.
+     */
+    pub async fn update_profile(&self, update: ProfileUpdate) -> Result<(), Error> {
+        self.send(ClientMessage::Profile { id: None, update: Some(update) }).await
+    }
+
+    /**
+     * Ask another user to open a peer to peer connection, they get an offer.
+     */
+    pub async fn request(&self, target: &str) -> Result<(), Error> {
+        self.send(ClientMessage::Request { target: target.to_string() }).await
+    }
+
+    /**
+     * Accept an offer, both sides get a confirm.
+     */
+    pub async fn accept(&self, offer: &str) -> Result<(), Error> {
+        self.send(ClientMessage::Offer { accept: true, id: offer.to_string() }).await
+    }
+
+    /**
+     * Decline an offer, both sides get a confirm.
+     */
+    pub async fn decline(&self, offer: &str) -> Result<(), Error> {
+        self.send(ClientMessage::Offer { accept: false, id: offer.to_string() }).await
+    }
+
+    /**
+     * Tell the other side of an accepted offer which port to connect to, they get a peer.
+     */
+    pub async fn session(&self, offer: &str, port: u16) -> Result<(), Error> {
+        self.send(ClientMessage::Session { offer: offer.to_string(), port }).await
+    }
+
+    /**
+     * Close the connection, the server removes the user right away instead of waiting for a resume.
+     */
+    pub async fn close(&self) -> Result<(), Error> {
+        self.write.lock().await.close().await?;
+        Ok(())
+    }
+}
+
+/**
+ * A connection to a flow server.
+ */
+pub struct Client {
+    sender: Sender,
+    events: Events,
+    pub me: Option<Me>,
+}
+
+impl Client {
+    /**
+     * Connect to a server, e.g. "ws://127.0.0.1:25656", and agree on the protocol.
+     */
+    pub async fn connect(url: &str) -> Result<Client, Error> {
+        let (stream, _) = connect_async(url).await?;
+        let (write, read) = stream.split();
+
+        let events = read
+            .filter_map(|message| async move {
+                match message {
+                    Ok(Message::Text(text)) => Some(serde_json::from_str(&text).unwrap_or_else(|err| {
+                        ServerMessage::Invalid { text: text.to_string(), error: err.to_string() }
+                    })),
+                    _ => None,
+                }
+            })
+            .boxed();
+
+        let mut client = Client {
+            sender: Sender { write: Arc::new(Mutex::new(write)) },
+            events,
+            me: None,
+        };
+
+        client.send(ClientMessage::Hello {
+            protocols: vec![protocol::PROTOCOL_VERSION],
+            features: protocol::FEATURES.iter().map(|feature| feature.to_string()).collect(),
+        }).await?;
+
+        loop {
+            match client.next().await.ok_or(Error::Closed)? {
+                ServerMessage::Hello { .. } => return Ok(client),
+                ServerMessage::Error { message, .. } => return Err(Error::Server(message)),
+                _ => {}
+            }
+        }
+    }
+
+    /**
+     * Wait for the next message from the server, None once the connection is closed.
+     */
+    pub async fn next(&mut self) -> Option<ServerMessage> {
+        self.events.next().await
+    }
+
+    /**
+     * Split the client to send and receive at the same time.
+     */
+    pub fn split(self) -> (Sender, Events) {
+        (self.sender, self.events)
+    }
+
+    /**
+     * Login as a user, with a password the name is registered to an account.
+     * Returns the users that are online.
+     */
+    pub async fn login(&mut self, name: &str, password: Option<&str>) -> Result<Vec<OnlineUser>, Error> {
+        self.send(ClientMessage::Login {
+            name: Some(name.to_string()),
+            password: password.map(String::from),
+            api_token: None,
+        }).await?;
+        self.logged_in().await
+    }
+
+    /**
+     * Login as a bot with the api token from the admin channel.
+     */
+    pub async fn login_bot(&mut self, api_token: &str) -> Result<Vec<OnlineUser>, Error> {
+        self.send(ClientMessage::Login { name: None, password: None, api_token: Some(api_token.to_string()) }).await?;
+        self.logged_in().await
+    }
+
+    /**
+     * Wait for the welcome and login messages.
+     */
+    async fn logged_in(&mut self) -> Result<Vec<OnlineUser>, Error> {
+        loop {
+            match self.next().await.ok_or(Error::Closed)? {
+                ServerMessage::Welcome { user, .. } => self.me = Some(user),
+                ServerMessage::Login { users, .. } => return Ok(users),
+                ServerMessage::Error { message, .. } => return Err(Error::Server(message)),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Deref for Client {
+    type Target = Sender;
+
+    fn deref(&self) -> &Sender {
+        &self.sender
+    }
+}
@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/**
+ * Version of the message protocol, bumped on breaking changes.
+ */
+pub const PROTOCOL_VERSION: u64 = 1;
+
+/**
+ * Oldest protocol version the server can still talk.
+ */
+pub const MIN_PROTOCOL_VERSION: u64 = 1;
+
+/**
+ * Optional features of the protocol.
+ */
+pub const FEATURES: [&str; 16] = [
+    "chat", "file", "p2p", "resume", "presence", "typing", "ack", "history", "threads", "reactions", "direct", "receipts", "offline", "blocks",
+    "rename", "profiles",
+];
+
+/**
+ * A user as they appear in most messages.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct User {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub bot: bool,
+}
+
+/**
+ * Who a client is logged in as.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Me {
+    pub id: String,
+    pub name: String,
+    pub role: String,
+    #[serde(default)]
+    pub bot: bool,
+}
+
+/**
+ * What others can see of the presence of a user.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Presence {
+    pub status: String,
+    pub text: Option<String>,
+}
+
+/**
+ * A user in the list of online users.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OnlineUser {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub bot: bool,
+    pub presence: Presence,
+}
+
+/**
+ * A chat message, direct messages have a target and replies a parent.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chat {
+    pub id: u64,
+    pub timestamp: u64,
+    pub sender: User,
+    #[serde(default)]
+    pub content: Option<String>,
+    #[serde(default)]
+    pub target: Option<String>,
+    #[serde(default)]
+    pub parent: Option<u64>,
+    #[serde(default)]
+    pub edited: Option<u64>,
+    #[serde(default)]
+    pub deleted: Option<u64>,
+    #[serde(default)]
+    pub reactions: HashMap<String, u64>,
+    #[serde(default)]
+    pub action: bool,
+}
+
+/**
+ * A file shared with everyone, the content is base64.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct File {
+    pub id: u64,
+    pub timestamp: u64,
+    pub sender: User,
+    pub name: String,
+    pub content: String,
+}
+
+/**
+ * An image file, the content is base64.
+ * Profiles everyone gets after a change leave the content out, ask for the profile to get it.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Avatar {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub content: String,
+}
+
+/**
+ * What a registered user tells others about themselves, guests have an empty profile.
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Profile {
+    pub display_name: Option<String>,
+    pub avatar: Option<Avatar>,
+    pub bio: Option<String>,
+    pub status: Option<String>,
+    pub timezone: Option<String>,
+}
+
+/**
+ * Changes to your own profile, fields that are None stay the same and an empty string clears them.
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProfileUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bio: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+/**
+ * The topic of the server and who set it, both are None without a topic.
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Topic {
+    pub topic: Option<String>,
+    pub by: Option<String>,
+}
+
+/**
+ * Messages the server sends to clients.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Hello { protocol: u64, features: Vec<String>, server: String, version: String },
+    Welcome { user: Me, server: String, version: String, protocol: u64, features: Vec<String>, limits: Value },
+    Login {
+        users: Vec<OnlineUser>,
+        #[serde(default)]
+        unread: HashMap<String, u64>,
+        #[serde(default)]
+        blocked: Vec<String>,
+        #[serde(default)]
+        token: Option<String>,
+        #[serde(default)]
+        topic: Topic,
+    },
+    Resume {
+        id: String,
+        token: String,
+        users: Vec<OnlineUser>,
+        #[serde(default)]
+        unread: HashMap<String, u64>,
+    },
+    Join { user: User },
+    Leave { user: User },
+    Reconnecting { user: User },
+    Resumed { user: User },
+    Chat(Chat),
+    File(File),
+    Edit { id: u64, edited: u64, content: String },
+    Delete { id: u64, deleted: u64 },
+    React {
+        id: u64,
+        emoji: String,
+        user: User,
+        remove: bool,
+        count: u64,
+        #[serde(default)]
+        timestamp: u64,
+    },
+    Read { user: User, id: u64 },
+    History { parent: Option<u64>, messages: Vec<Chat> },
+    Presence { user: User, presence: Presence },
+    Typing { user: User, typing: bool },
+    Ack { nonce: String, id: u64, timestamp: u64, duplicate: bool },
+    Offer { origin: String, id: String },
+    Confirm { accept: bool, offer: String },
+    Peer { addr: String, offer: String },
+    Announcement { content: String },
+    Role { role: String },
+    Block {
+        target: String,
+        remove: bool,
+        #[serde(default)]
+        chat: bool,
+    },
+    Command { command: String, content: String },
+    Topic(Topic),
+    Rename { user: User, old: String },
+    Profile { id: String, name: String, profile: Profile },
+    Shutdown { reconnect: Option<String> },
+    Error { source: String, message: String },
+    /**
+     * A message the client couldn't decode, with its text and why. The server never sends this.
+     */
+    #[serde(skip)]
+    Invalid { text: String, error: String },
+    #[serde(other)]
+    Unknown,
+}
+
+/**
+ * Messages clients send to the server, fields that are None are left out.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Hello { protocols: Vec<u64>, features: Vec<String> },
+    Login {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        password: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        api_token: Option<String>,
+    },
+    Resume { token: String },
+    Chat {
+        content: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        target: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        parent: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        nonce: Option<String>,
+    },
+    Edit {
+        id: u64,
+        content: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        nonce: Option<String>,
+    },
+    Delete {
+        id: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        nonce: Option<String>,
+    },
+    React {
+        id: u64,
+        emoji: String,
+        remove: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        nonce: Option<String>,
+    },
+    Read { id: u64 },
+    History {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        before: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        parent: Option<u64>,
+        limit: u64,
+    },
+    Presence {
+        status: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        text: Option<String>,
+    },
+    Typing {
+        typing: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        target: Option<String>,
+    },
+    File {
+        name: String,
+        content: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        nonce: Option<String>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        avatar: bool,
+    },
+    Request { target: String },
+    Offer { accept: bool, id: String },
+    Session { offer: String, port: u16 },
+    Block {
+        target: String,
+        remove: bool,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        chat: bool,
+    },
+    Rename { name: String },
+    Profile {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        update: Option<ProfileUpdate>,
+    },
+}
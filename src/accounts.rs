@@ -0,0 +1,295 @@
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{bans, names, roles::Role, store, USERS};
+
+/**
+ * Rounds of pbkdf2 used to hash passwords.
+ */
+const HASH_ROUNDS: u32 = 100_000;
+
+pub const MIN_PASSWORD_LENGTH: usize = 6;
+pub const MAX_PASSWORD_LENGTH: usize = 128;
+
+/**
+ * A registered user, their id stays the same between logins.
+ * Bots login with an api token instead of a password.
+ */
+#[derive(Debug)]
+pub struct Account {
+    pub id: String,
+    pub name: String,
+    pub salt: String,
+    pub hash: String,
+    pub role: Role,
+    pub bot: Option<String>,
+}
+
+/**
+ * Who is logging in.
+ */
+pub struct Identity {
+    pub id: String,
+    pub name: String,
+    pub role: Role,
+    pub bot: bool,
+}
+
+pub static mut ACCOUNTS: Vec<Account> = Vec::new();
+
+/**
+ * Load the accounts from the store.
+ */
+pub fn load() {
+    let Some(Value::Array(accounts)) = store::load("accounts") else { return };
+
+    let accounts = accounts.iter().filter_map(|account| Some(Account {
+        id: account["id"].as_str()?.to_string(),
+        name: account["name"].as_str()?.to_string(),
+        salt: account["salt"].as_str()?.to_string(),
+        hash: account["hash"].as_str()?.to_string(),
+        role: account["role"].as_str().and_then(Role::parse).unwrap_or(Role::Member),
+        bot: account["bot"].as_str().map(String::from),
+    }));
+
+    unsafe { ACCOUNTS = accounts.collect(); }
+}
+
+/**
+ * Write the accounts to the store.
+ */
+pub fn save() {
+    let accounts: Vec<Value> = unsafe {
+        ACCOUNTS.iter().map(|account| json!({
+            "id": account.id,
+            "name": account.name,
+            "salt": account.salt,
+            "hash": account.hash,
+            "role": account.role.as_str(),
+            "bot": account.bot
+        })).collect()
+    };
+
+    store::save("accounts", &Value::Array(accounts));
+}
+
+/**
+ * Hash a password with a salt, this is slow on purpose so it runs on the blocking thread pool.
+ */
+async fn hash(password: &str, salt: &str) -> String {
+    let (password, salt) = (password.to_string(), salt.to_string());
+
+    let out = tokio::task::spawn_blocking(move || {
+        let mut out = [0u8; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt.as_bytes(), HASH_ROUNDS, &mut out);
+        out
+    }).await.expect("Hashing doesn't panic");
+
+    out.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/**
+ * Compare two hashes in constant time, so how long it takes doesn't tell how much matched.
+ */
+fn hashes_match(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/**
+ * Hash an api token, they're random enough to not need a salt.
+ */
+fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/**
+ * Get an account by its id.
+ */
+pub fn get<'a>(id: &str) -> Option<&'a Account> {
+    unsafe { ACCOUNTS.iter().find(|account| account.id == id) }
+}
+
+/**
+ * Get the account of a name, names that look alike belong to the same account.
+ */
+pub fn find<'a>(name: &str) -> Option<&'a Account> {
+    let skeleton = names::skeleton(name);
+    unsafe { ACCOUNTS.iter().find(|account| names::skeleton(&account.name) == skeleton) }
+}
+
+/**
+ * Check the password of an account by its id.
+ */
+pub async fn verify(id: &str, password: &str) -> bool {
+    let Some(account) = get(id).filter(|account| account.bot.is_none()) else { return false };
+
+    // The accounts can change while hashing, so don't hold on to this one:
+    let (salt, expected) = (account.salt.clone(), account.hash.clone());
+    hashes_match(&hash(password, &salt).await, &expected)
+}
+
+/**
+ * Register a new account for a (validated) name.
+ */
+pub async fn register<'a>(name: String, password: &str) -> Result<&'a Account, String> {
+    let length = password.chars().count();
+    if !(MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&length) {
+        return Err(format!("Password must be between {} and {} characters", MIN_PASSWORD_LENGTH, MAX_PASSWORD_LENGTH));
+    }
+
+    let salt = Uuid::new_v4().to_string();
+    let hash = hash(password, &salt).await;
+
+    // Someone else could have registered the name while hashing:
+    if find(&name).is_some() {
+        return Err(String::from("Name is already registered"));
+    }
+
+    let account = Account {
+        id: Uuid::new_v4().to_string(),
+        name,
+        hash,
+        salt,
+        role: Role::Member,
+        bot: None,
+    };
+
+    unsafe { ACCOUNTS.push(account); }
+    save();
+
+    Ok(unsafe { ACCOUNTS.last().unwrap() })
+}
+
+/**
+ * Create a bot account, or give an existing bot a new api token.
+ * The token is only returned here, the account keeps its hash.
+ */
+pub fn create_bot(name: &str) -> Result<String, String> {
+    let name = names::validate(&json!(name))?;
+    let token = format!("{}{}", Uuid::new_v4().to_simple(), Uuid::new_v4().to_simple());
+
+    unsafe {
+        match find(&name).map(|account| account.id.clone()) {
+            Some(id) => {
+                let account = ACCOUNTS.iter_mut().find(|account| account.id == id).ok_or("Account not found")?;
+                if account.bot.is_none() {
+                    return Err(String::from("Name is registered to a user"));
+                }
+                account.bot = Some(hash_token(&token));
+            }
+            None => ACCOUNTS.push(Account {
+                id: Uuid::new_v4().to_string(),
+                name,
+                salt: String::new(),
+                hash: String::new(),
+                role: Role::Member,
+                bot: Some(hash_token(&token)),
+            }),
+        }
+    }
+    save();
+
+    Ok(token)
+}
+
+/**
+ * Give an account, found by its id or name, a new role.
+ */
+pub fn set_role<'a>(account: &str, role: Role) -> Result<&'a Account, String> {
+    let id = get(account).or_else(|| find(account)).ok_or("Account not found")?.id.clone();
+    let account = unsafe { ACCOUNTS.iter_mut().find(|account| account.id == id).ok_or("Account not found")? };
+
+    account.role = role;
+    save();
+
+    Ok(account)
+}
+
+/**
+ * Give an account a new name, does nothing for users without an account.
+ */
+pub fn rename(id: &str, name: &str) {
+    let Some(account) = (unsafe { ACCOUNTS.iter_mut().find(|account| account.id == id) }) else { return };
+
+    account.name = name.to_string();
+    save();
+}
+
+/**
+ * Check a name a user asked for at login or with a rename, before it's registered or made unique.
+ */
+pub fn requested_name(name: &Value) -> Result<String, String> {
+    let name = names::validate(name)?;
+    if bans::is_banned_name(&name) {
+        return Err(String::from("Name is banned"));
+    }
+    Ok(name)
+}
+
+/**
+ * Work out who is logging in from their login message.
+ * Bots use their api token, registered names need their password,
+ * a password with a new name registers it and anything else is a guest.
+ */
+pub async fn identify(login: &Value) -> Result<Identity, String> {
+    if let Some(token) = login["api_token"].as_str() {
+        let token = hash_token(token);
+        let account = unsafe { ACCOUNTS.iter().find(|account| account.bot.as_ref() == Some(&token)) };
+        let account = account.ok_or("Invalid api token")?;
+        if bans::is_banned_name(&account.name) {
+            return Err(String::from("User is banned"));
+        }
+        return online(account, true);
+    }
+
+    let name = requested_name(&login["name"])?;
+    let password = match &login["password"] {
+        Value::Null => None,
+        Value::String(password) => Some(password.as_str()),
+        _ => return Err(String::from("Password must be a string")),
+    };
+
+    match (find(&name), password) {
+        (Some(account), Some(password)) => {
+            let id = account.id.clone();
+            if !verify(&id, password).await {
+                return Err(String::from("Wrong password"));
+            }
+            online(get(&id).ok_or("Account not found")?, false)
+        }
+        (Some(_), None) => Err(String::from("Name is registered (Missing password)")),
+        (None, Some(password)) => {
+            if names::is_taken(&name) {
+                return Err(String::from("Name is already in use"));
+            }
+            let account = register(name, password).await?;
+            online(account, false)
+        }
+        (None, None) => Ok(Identity {
+            id: Uuid::new_v4().to_string(),
+            name: names::make_unique(name)?,
+            role: Role::Guest,
+            bot: false,
+        }),
+    }
+}
+
+/**
+ * The identity of an account that's about to come online.
+ */
+fn online(account: &Account, bot: bool) -> Result<Identity, String> {
+    if unsafe { USERS.iter().any(|user| user.id == account.id) } {
+        return Err(String::from("Account is already logged in"));
+    }
+    if names::is_taken(&account.name) {
+        return Err(String::from("Name is already in use"));
+    }
+
+    Ok(Identity {
+        id: account.id.clone(),
+        name: account.name.clone(),
+        role: account.role,
+        bot,
+    })
+}
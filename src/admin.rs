@@ -0,0 +1,262 @@
+use colored::*;
+use serde_json::json;
+
+use crate::{
+    accounts,
+    bans::{self, Ban},
+    events::{self, Event},
+    history, info, messages, names,
+    roles::Role,
+    send::{disconnect, send_all, send_only, send_related, SERVER_ADDR},
+    OFFERS, USERS,
+};
+
+/**
+ * Serve the admin command channel on a local unix socket.
+ * Every line is a command, every reply ends with an "ok" or "error: ..." line.
+ */
+#[cfg(unix)]
+pub async fn serve(path: String) {
+    use std::{fs, os::unix::fs::{FileTypeExt, PermissionsExt}};
+    use tokio::{io::{AsyncBufReadExt, AsyncWriteExt, BufReader}, net::UnixListener};
+
+    // Remove a stale socket left behind by a previous run, anything else at the path is left alone.
+    if fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        let _ = fs::remove_file(&path);
+    }
+
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            info::info("Admin".red(), format!("Failed to bind to {} ({})", path, err));
+            return;
+        }
+    };
+
+    // Only the user running the server can send admin commands.
+    if let Err(err) = fs::set_permissions(&path, fs::Permissions::from_mode(0o600)) {
+        info::info("Admin".red(), format!("Failed to restrict {} ({})", path, err));
+        return;
+    }
+    info::info("Admin".white(), format!("Listening on: {}", path));
+
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(async move {
+            let (read, mut write) = stream.into_split();
+            let mut lines = BufReader::new(read).lines();
+
+            while let Ok(Some(line)) = lines.next_line().await {
+                let reply = execute(line.trim()).await;
+                if write.write_all(reply.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn serve(path: String) {
+    info::info("Admin".red(), format!("Unix sockets are not supported on this platform ({})", path));
+}
+
+/**
+ * Execute a single admin command and return the reply.
+ */
+pub async fn execute(command: &str) -> String {
+    if command.is_empty() {
+        return String::new();
+    }
+    info::info("Admin".yellow(), String::from(command));
+
+    let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+    let args = args.trim();
+
+    let result = match name {
+        "list" => list(args),
+        "kick" => kick(args).await,
+        "ban" => ban(args).await,
+        "unban" => unban(args),
+        "broadcast" => broadcast(args).await,
+        "delete" => delete(args).await,
+        "role" => role(args).await,
+        "bot" => bot(args),
+        "help" => Ok(String::from("list users|offers|bans|accounts\nkick <id>\nban <ip|name>\nunban <ip|name>\nbroadcast <message>\ndelete <message id>\nrole <account id|name> <admin|moderator|member|guest>\nbot <name>\n")),
+        _ => Err(format!("Unknown command \"{}\"", name)),
+    };
+
+    match result {
+        Ok(out) => format!("{}ok\n", out),
+        Err(err) => format!("error: {}\n", err),
+    }
+}
+
+/**
+ * List the online users, pending offers, bans or accounts.
+ */
+fn list(what: &str) -> Result<String, String> {
+    let mut out = String::new();
+
+    unsafe {
+        match what {
+            "users" => for user in USERS.iter() {
+                out += &format!("{} {} {}{} {}\n", user.id, user.addr, user.role, if user.bot { " bot" } else { "" }, user.name);
+            },
+            "offers" => for offer in OFFERS.iter() {
+                out += &format!("{} {} -> {}\n", offer.id, offer.origin, offer.target);
+            },
+            "bans" => for ban in bans::BANS.iter() {
+                match ban {
+                    Ban::Ip(ip) => out += &format!("ip {}\n", ip),
+                    Ban::Name(name) => out += &format!("name {}\n", name),
+                }
+            },
+            "accounts" => for account in accounts::ACCOUNTS.iter() {
+                out += &format!("{} {}{} {}\n", account.id, account.role, if account.bot.is_some() { " bot" } else { "" }, account.name);
+            },
+            _ => return Err(String::from("Usage: list users|offers|bans|accounts")),
+        }
+    }
+
+    Ok(out)
+}
+
+/**
+ * Disconnect a user by their id.
+ */
+pub(crate) async fn kick(id: &str) -> Result<String, String> {
+    let addr = unsafe { USERS.iter().find(|user| user.id == id).map(|user| user.addr) };
+
+    match addr {
+        Some(addr) => {
+            disconnect(addr, "Kicked by an admin").await;
+            Ok(String::new())
+        }
+        None => Err(String::from("User not found")),
+    }
+}
+
+/**
+ * Ban an ip address or name and disconnect everyone matching it.
+ */
+pub(crate) async fn ban(target: &str) -> Result<String, String> {
+    if target.is_empty() {
+        return Err(String::from("Usage: ban <ip|name>"));
+    }
+
+    let ban = Ban::parse(target);
+    let matches: Vec<_> = unsafe {
+        USERS.iter()
+            .filter(|user| match &ban {
+                Ban::Ip(ip) => user.addr.ip() == *ip,
+                Ban::Name(name) => names::skeleton(&user.name) == names::skeleton(name),
+            })
+            .map(|user| user.addr)
+            .collect()
+    };
+
+    if !bans::add(ban) {
+        return Err(String::from("Already banned"));
+    }
+
+    for addr in matches.iter() {
+        disconnect(*addr, "Banned by an admin").await;
+    }
+
+    Ok(format!("Disconnected {} user(s)\n", matches.len()))
+}
+
+/**
+ * Lift a ban on an ip address or name.
+ */
+fn unban(target: &str) -> Result<String, String> {
+    match bans::remove(Ban::parse(target)) {
+        true => Ok(String::new()),
+        false => Err(String::from("Not banned")),
+    }
+}
+
+/**
+ * Send a server wide announcement to all users.
+ * { type: "announcement", content: "message" }
+ */
+pub(crate) async fn broadcast(content: &str) -> Result<String, String> {
+    if content.is_empty() {
+        return Err(String::from("Usage: broadcast <message>"));
+    }
+
+    let announcement_json = json!({
+        "type": "announcement",
+        "content": content
+    });
+
+    send_all(SERVER_ADDR, announcement_json.to_string()).await;
+    Ok(String::new())
+}
+
+/**
+ * Delete a chat message of any user.
+ */
+async fn delete(id: &str) -> Result<String, String> {
+    let id: u64 = id.parse().map_err(|_| String::from("Usage: delete <message id>"))?;
+
+    match history::get(id) {
+        Some(entry) if entry["deleted"] != serde_json::Value::Null => return Err(String::from("Message was already deleted")),
+        Some(_) => {}
+        None => return Err(String::from("Message not found")),
+    }
+
+    let deleted = history::delete(id, "admin", messages::timestamp()).ok_or("Message not found")?;
+    let entry = history::get(id).ok_or("Message not found")?.clone();
+
+    let delete_json = json!({
+        "type": "delete",
+        "id": id,
+        "deleted": deleted
+    });
+
+    send_related(SERVER_ADDR, &entry, delete_json.to_string()).await;
+    events::emit(Event::ChatDeleted { id, by: String::from("admin") });
+    Ok(String::new())
+}
+
+/**
+ * Give an account a new role, it applies right away if the user is online.
+ * { type: "role", role: "moderator" }
+ */
+pub(crate) async fn role(args: &str) -> Result<String, String> {
+    let usage = || String::from("Usage: role <account id|name> <admin|moderator|member|guest>");
+    let (account, role) = args.rsplit_once(' ').ok_or_else(usage)?;
+    let role = Role::parse(role.trim()).ok_or_else(usage)?;
+
+    let account = accounts::set_role(account.trim(), role)?;
+
+    let addr = unsafe {
+        USERS.iter_mut().find(|user| user.id == account.id).map(|user| {
+            user.role = role;
+            user.addr
+        })
+    };
+
+    if let Some(addr) = addr {
+        let role_json = json!({
+            "type": "role",
+            "role": role.as_str()
+        });
+        send_only(addr, role_json.to_string()).await;
+    }
+
+    Ok(String::new())
+}
+
+/**
+ * Create a bot account and print its api token, for an existing bot the old token stops working.
+ */
+fn bot(name: &str) -> Result<String, String> {
+    if name.is_empty() {
+        return Err(String::from("Usage: bot <name>"));
+    }
+
+    let token = accounts::create_bot(name)?;
+    Ok(format!("{}\n", token))
+}
@@ -0,0 +1,105 @@
+use std::net::IpAddr;
+
+use serde_json::{json, Value};
+
+use crate::{names, store};
+
+pub static mut BANS: Vec<Ban> = Vec::new();
+
+#[derive(Debug, PartialEq)]
+pub enum Ban {
+    Ip(IpAddr),
+    Name(String),
+}
+
+impl Ban {
+    /**
+     * Parse a ban target, anything that isn't an ip address is a name.
+     */
+    pub fn parse(target: &str) -> Ban {
+        match target.parse::<IpAddr>() {
+            Ok(ip) => Ban::Ip(ip),
+            Err(_) => Ban::Name(target.to_lowercase()),
+        }
+    }
+}
+
+/**
+ * Load the ban list from the store.
+ */
+pub fn load() {
+    let Some(value) = store::load("bans") else { return };
+
+    let ips = value["ips"].as_array().into_iter().flatten()
+        .filter_map(|ip| ip.as_str()?.parse().ok())
+        .map(Ban::Ip);
+    let names = value["names"].as_array().into_iter().flatten()
+        .filter_map(|name| Some(Ban::Name(name.as_str()?.to_lowercase())));
+
+    unsafe {
+        BANS = ips.chain(names).collect();
+    }
+}
+
+/**
+ * Write the ban list to the store.
+ */
+pub fn save() {
+    let mut ips: Vec<Value> = Vec::new();
+    let mut names: Vec<Value> = Vec::new();
+
+    unsafe {
+        for ban in BANS.iter() {
+            match ban {
+                Ban::Ip(ip) => ips.push(json!(ip.to_string())),
+                Ban::Name(name) => names.push(json!(name)),
+            }
+        }
+    }
+
+    store::save("bans", &json!({ "ips": ips, "names": names }));
+}
+
+/**
+ * Add a ban, returns false if it already existed.
+ */
+pub fn add(ban: Ban) -> bool {
+    unsafe {
+        if BANS.contains(&ban) {
+            return false;
+        }
+        BANS.push(ban);
+    }
+    save();
+    true
+}
+
+/**
+ * Remove a ban, returns false if it didn't exist.
+ */
+pub fn remove(ban: Ban) -> bool {
+    unsafe {
+        let len = BANS.len();
+        BANS.retain(|other| *other != ban);
+        if BANS.len() == len {
+            return false;
+        }
+    }
+    save();
+    true
+}
+
+/**
+ * Check if an ip address is banned.
+ */
+pub fn is_banned_ip(ip: IpAddr) -> bool {
+    unsafe { BANS.contains(&Ban::Ip(ip)) }
+}
+
+/**
+ * Check if a name is banned, names that look alike are banned too.
+ */
+pub fn is_banned_name(name: &str) -> bool {
+    let skeleton = names::skeleton(name);
+    unsafe { BANS.iter().any(|ban| matches!(ban, Ban::Name(banned) if names::skeleton(banned) == skeleton)) }
+}
@@ -0,0 +1,195 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde_json::{json, Map, Value};
+
+use crate::{accounts, store};
+
+/**
+ * Most users a single user can block.
+ */
+pub const MAX_BLOCKS: usize = 1000;
+
+/**
+ * A user that doesn't want to hear from another user, with chat their public chat is hidden too.
+ */
+#[derive(Debug)]
+pub struct Block {
+    pub user: String,
+    pub blocked: String,
+    pub chat: bool,
+}
+
+pub static mut BLOCKS: Vec<Block> = Vec::new();
+static DIRTY: AtomicBool = AtomicBool::new(false);
+
+/**
+ * Load the block lists from the store.
+ */
+pub fn load() {
+    let Some(Value::Object(users)) = store::load("blocks") else { return };
+
+    let mut blocks = Vec::new();
+    for (user, blocked) in users.iter() {
+        for blocked in blocked.as_array().into_iter().flatten() {
+            if let Some(id) = blocked["id"].as_str() {
+                blocks.push(Block { user: user.clone(), blocked: id.to_string(), chat: blocked["chat"] == true });
+            }
+        }
+    }
+
+    unsafe { BLOCKS = blocks; }
+}
+
+/**
+ * Write the block lists to the store if they changed, only blocks between registered users are kept.
+ * { "user_id": [{ id: "blocked_id", chat: false }] }
+ */
+pub fn flush() {
+    if !DIRTY.swap(false, Ordering::Relaxed) {
+        return;
+    }
+
+    let mut users = Map::new();
+    unsafe {
+        for block in BLOCKS.iter().filter(|block| accounts::get(&block.user).is_some() && accounts::get(&block.blocked).is_some()) {
+            let blocked = users.entry(block.user.clone()).or_insert(json!([]));
+            if let Some(blocked) = blocked.as_array_mut() {
+                blocked.push(json!({ "id": block.blocked, "chat": block.chat }));
+            }
+        }
+    }
+
+    store::save("blocks", &Value::Object(users));
+}
+
+/**
+ * Check if a user has blocked another user.
+ */
+pub fn blocks(user: &str, other: &str) -> bool {
+    unsafe { BLOCKS.iter().any(|block| block.user == user && block.blocked == other) }
+}
+
+/**
+ * Check if a user doesn't want to see a chat message.
+ * Direct messages of blocked users are always hidden, their public chat only if the block includes it.
+ */
+pub fn hides(user: &str, entry: &Value) -> bool {
+    let author = entry["sender"]["id"].as_str().unwrap_or_default();
+    unsafe {
+        BLOCKS.iter().any(|block| block.user == user && block.blocked == author && (block.chat || entry["target"] != Value::Null))
+    }
+}
+
+/**
+ * Get everyone a user has blocked.
+ */
+pub fn list(user: &str) -> Vec<String> {
+    unsafe {
+        BLOCKS.iter()
+            .filter(|block| block.user == user)
+            .map(|block| block.blocked.clone())
+            .collect()
+    }
+}
+
+/**
+ * Block another user, or change if their chat is hidden. Returns false if nothing changed.
+ */
+pub fn block(user: &str, other: &str, chat: bool) -> Result<bool, String> {
+    unsafe {
+        if let Some(block) = BLOCKS.iter_mut().find(|block| block.user == user && block.blocked == other) {
+            if block.chat == chat {
+                return Ok(false);
+            }
+            block.chat = chat;
+            DIRTY.store(true, Ordering::Relaxed);
+            return Ok(true);
+        }
+
+        if BLOCKS.iter().filter(|block| block.user == user).count() >= MAX_BLOCKS {
+            return Err(format!("Cannot block more than {} users", MAX_BLOCKS));
+        }
+        BLOCKS.push(Block { user: user.to_string(), blocked: other.to_string(), chat });
+    }
+
+    DIRTY.store(true, Ordering::Relaxed);
+    Ok(true)
+}
+
+/**
+ * Unblock another user, returns false if they weren't blocked.
+ */
+pub fn unblock(user: &str, other: &str) -> bool {
+    unsafe {
+        let len = BLOCKS.len();
+        BLOCKS.retain(|block| block.user != user || block.blocked != other);
+        if BLOCKS.len() == len {
+            return false;
+        }
+    }
+
+    DIRTY.store(true, Ordering::Relaxed);
+    true
+}
+
+/**
+ * Forget the blocks of and on a user that won't come back, like a guest that left.
+ */
+pub fn forget(user: &str) {
+    unsafe { BLOCKS.retain(|block| block.user != user && block.blocked != user); }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use serial_test::serial;
+
+    use super::{block, blocks, forget, hides, list, unblock, BLOCKS};
+
+    #[test]
+    #[serial]
+    fn block_and_unblock() {
+        unsafe { BLOCKS.clear(); }
+
+        assert_eq!(block("alice", "bob", false), Ok(true));
+        assert_eq!(block("alice", "bob", false), Ok(false));
+        assert_eq!(block("alice", "bob", true), Ok(true));
+        assert!(blocks("alice", "bob"));
+        assert!(!blocks("bob", "alice"));
+        assert_eq!(list("alice"), ["bob"]);
+
+        assert!(unblock("alice", "bob"));
+        assert!(!unblock("alice", "bob"));
+        assert!(list("alice").is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn chat_is_only_hidden_with_the_chat_flag() {
+        unsafe { BLOCKS.clear(); }
+        let public = json!({ "sender": { "id": "bob" }, "target": null });
+        let direct = json!({ "sender": { "id": "bob" }, "target": "alice" });
+
+        assert!(!hides("alice", &public));
+        block("alice", "bob", false).unwrap();
+        assert!(!hides("alice", &public));
+        assert!(hides("alice", &direct));
+        assert!(!hides("carol", &direct));
+
+        block("alice", "bob", true).unwrap();
+        assert!(hides("alice", &public));
+    }
+
+    #[test]
+    #[serial]
+    fn forget_removes_both_sides() {
+        unsafe { BLOCKS.clear(); }
+        block("alice", "guest", false).unwrap();
+        block("guest", "bob", false).unwrap();
+        block("alice", "bob", false).unwrap();
+
+        forget("guest");
+        assert_eq!(list("alice"), ["bob"]);
+        assert!(list("guest").is_empty());
+    }
+}
@@ -0,0 +1,415 @@
+use std::{collections::BTreeMap, future::Future, pin::Pin};
+
+use colored::Color;
+use serde_json::{json, Value};
+
+use crate::{
+    admin,
+    bans::Ban,
+    handlers::{Context, HandlerFuture, MessageHandler},
+    info::{self, get_user},
+    names,
+    presence::Status,
+    protocol,
+    roles::Permission,
+    send::{send_all, send_only, SERVER_ADDR},
+    topic, trafic, FluxUser, USERS,
+};
+
+/**
+ * What a command replies to the user who used it, or why it failed.
+ */
+pub type CommandResult = Result<Option<String>, String>;
+
+pub type CommandFuture = Pin<Box<dyn Future<Output = CommandResult> + Send>>;
+
+/**
+ * Runs a slash command, it gets the arguments, the chat message they came in and its context.
+ * Async functions and closures taking those are commands already.
+ */
+pub trait ChatCommand: Send + Sync {
+    fn run(&self, args: String, json: Value, ctx: Context) -> CommandFuture;
+}
+
+impl<F, Fut> ChatCommand for F
+where
+    F: Fn(String, Value, Context) -> Fut + Send + Sync,
+    Fut: Future<Output = CommandResult> + Send + 'static,
+{
+    fn run(&self, args: String, json: Value, ctx: Context) -> CommandFuture {
+        Box::pin(self(args, json, ctx))
+    }
+}
+
+struct Command {
+    usage: String,
+    description: String,
+    permission: Option<Permission>,
+    handler: Box<dyn ChatCommand>,
+}
+
+/**
+ * The slash commands by name, without the slash.
+ */
+#[derive(Default)]
+pub struct Commands {
+    commands: BTreeMap<String, Command>,
+}
+
+impl Commands {
+    /**
+     * The commands flow knows about, /help is always there.
+     */
+    pub fn builtin() -> Commands {
+        let mut commands = Commands::default();
+
+        commands.register("nick", "<name>", "Change your name", None, nick);
+        commands.register("me", "<action>", "Tell everyone what you're doing", Some(Permission::Chat), me);
+        commands.register("who", "", "List the online users", None, who);
+        commands.register("msg", "<name> <message>", "Send a direct message", Some(Permission::Chat), msg);
+        commands.register("join", "<room>", "Join a room (there is only one)", None, join);
+        commands.register("topic", "[topic]", "Show the topic, moderators can change it", None, topic);
+        commands.register("kick", "<name>", "Disconnect a user", Some(Permission::Moderate), kick);
+        commands.register("ban", "<name|ip>", "Ban a name or ip address", Some(Permission::Moderate), ban);
+        commands.register("announce", "<message>", "Send an announcement to everyone", Some(Permission::Moderate), announce);
+        commands.register("role", "<name> <role>", "Give an account a new role", Some(Permission::Administer), role);
+
+        commands
+    }
+
+    /**
+     * Register a command, this replaces the command it had before.
+     * Users whose role doesn't have the permission can't use or see it.
+     */
+    pub fn register(
+        &mut self,
+        name: &str,
+        usage: &str,
+        description: &str,
+        permission: Option<Permission>,
+        handler: impl ChatCommand + 'static,
+    ) {
+        self.commands.insert(name.to_lowercase(), Command {
+            usage: usage.to_string(),
+            description: description.to_string(),
+            permission,
+            handler: Box::new(handler),
+        });
+    }
+
+    /**
+     * List the commands a user can use.
+     */
+    fn help(&self, user: &FluxUser) -> String {
+        let mut help = String::from("/help - List the commands");
+        for (name, command) in self.commands.iter() {
+            if command.permission.is_none_or(|permission| user.role.can(permission)) {
+                help += &format!("\n/{}{}{} - {}", name, if command.usage.is_empty() { "" } else { " " }, command.usage, command.description);
+            }
+        }
+        help
+    }
+}
+
+/**
+ * Handles chat messages, the ones starting with a slash run a command instead of being send.
+ * Start a message with two slashes to send it with one.
+ */
+pub struct ChatHandler {
+    commands: Commands,
+    chat: Box<dyn MessageHandler>,
+}
+
+impl ChatHandler {
+    pub fn new(commands: Commands, chat: Box<dyn MessageHandler>) -> ChatHandler {
+        ChatHandler { commands, chat }
+    }
+}
+
+/**
+ * What a chat message turns out to be.
+ */
+#[derive(Debug, PartialEq)]
+enum Input {
+    Chat,
+    /**
+     * Chat starting with two slashes, with the first one removed.
+     */
+    Escaped(String),
+    /**
+     * A lowercase command name and its trimmed arguments.
+     */
+    Command(String, String),
+}
+
+fn parse(content: &str) -> Input {
+    let Some(command) = content.strip_prefix('/') else { return Input::Chat };
+    if command.starts_with('/') {
+        return Input::Escaped(command.to_string());
+    }
+
+    let (name, args) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+    Input::Command(name.to_lowercase(), args.trim().to_string())
+}
+
+impl MessageHandler for ChatHandler {
+    fn handle(&self, mut json: Value, ctx: Context) -> HandlerFuture {
+        let (name, args) = match parse(json["content"].as_str().unwrap_or_default()) {
+            Input::Chat => return self.chat.handle(json, ctx),
+            Input::Escaped(content) => {
+                json["content"] = json!(content);
+                return self.chat.handle(json, ctx);
+            }
+            Input::Command(name, args) => (name, args),
+        };
+        let user = get_user(ctx.addr);
+        info::user_info(ctx.addr, format!("/{} {}", name, args), Color::Yellow);
+
+        let result: CommandFuture = match self.commands.commands.get(&name) {
+            _ if name == "help" => {
+                let help = self.commands.help(user);
+                Box::pin(async move { Ok(Some(help)) })
+            }
+            Some(command) if command.permission.is_some_and(|permission| !user.role.can(permission)) => {
+                let err = format!("Access declined ({} can't use /{})", user.role, name);
+                Box::pin(async move { Err(err) })
+            }
+            Some(command) => command.handler.run(args, json, ctx.clone()),
+            None => {
+                let err = format!("Unknown command \"/{}\" (Try /help)", name);
+                Box::pin(async move { Err(err) })
+            }
+        };
+
+        // Replies only go to the user who used the command, if they're still here:
+        Box::pin(async move {
+            match result.await {
+                Ok(Some(content)) if info::user_exists(ctx.addr) => {
+                    let reply_json = json!({
+                        "type": "command",
+                        "command": name,
+                        "content": content
+                    });
+                    send_only(ctx.addr, reply_json.to_string()).await;
+                    None
+                }
+                Ok(_) => None,
+                Err(err) => Some(err),
+            }
+        })
+    }
+}
+
+/**
+ * Find an online user by name, names that look alike match.
+ */
+fn find_user<'a>(name: &str) -> Option<&'a FluxUser> {
+    let skeleton = names::skeleton(name);
+    unsafe { USERS.iter().find(|user| names::skeleton(&user.name) == skeleton) }
+}
+
+/**
+ * Change the name of the user, the same as a rename message.
+ */
+async fn nick(args: String, _json: Value, ctx: Context) -> CommandResult {
+    if !protocol::has_feature(ctx.addr, "rename") {
+        return Err(String::from("Feature \"rename\" wasn't negotiated"));
+    }
+    trafic::rename(json!({ "name": args }), ctx.addr).await.map_or(Ok(None), Err)
+}
+
+/**
+ * Send the action as a chat message that clients show as "* name action".
+ */
+async fn me(args: String, mut json: Value, ctx: Context) -> CommandResult {
+    if args.is_empty() {
+        return Err(String::from("Usage: /me <action>"));
+    }
+
+    json["content"] = json!(args);
+    json["action"] = json!(true);
+    trafic::chat(json, ctx.addr).await.map_or(Ok(None), Err)
+}
+
+/**
+ * List the online users with their role and status, invisible users are left out.
+ */
+async fn who(_args: String, _json: Value, ctx: Context) -> CommandResult {
+    let users: Vec<String> = unsafe {
+        USERS.iter()
+            .filter(|user| user.presence.status != Status::Invisible || user.addr == ctx.addr)
+            .map(|user| format!("{} ({}{}, {})", user.name, user.role, if user.bot { ", bot" } else { "" }, user.presence.status.public_str()))
+            .collect()
+    };
+
+    Ok(Some(format!("{} online: {}", users.len(), users.join(", "))))
+}
+
+/**
+ * Send a direct message to a user by name, names can contain spaces so the longest match wins.
+ */
+async fn msg(args: String, mut json: Value, ctx: Context) -> CommandResult {
+    if !protocol::has_feature(ctx.addr, "direct") {
+        return Err(String::from("Feature \"direct\" wasn't negotiated"));
+    }
+    let target = unsafe {
+        USERS.iter()
+            .filter_map(|user| Some((user, strip_name(&args, &user.name)?)))
+            .max_by_key(|(user, _)| user.name.len())
+    };
+    let Some((target, content)) = target else { return Err(String::from("Usage: /msg <name> <message> (User not found)")) };
+
+    json["content"] = json!(content);
+    json["target"] = json!(target.id);
+    trafic::chat(json, ctx.addr).await.map_or(Ok(None), Err)
+}
+
+/**
+ * Strip a name and the space after it from the start of the arguments, ignoring case.
+ * Returns the rest of the arguments, or None if they don't start with the name.
+ */
+fn strip_name<'a>(args: &'a str, name: &str) -> Option<&'a str> {
+    let mut name_chars = name.chars().flat_map(char::to_lowercase);
+    for (index, c) in args.char_indices() {
+        // Some characters lowercase to more than one, compare them one by one:
+        let mut lowercase = c.to_lowercase();
+        let matched = match name_chars.next() {
+            Some(expected) => lowercase.next() == Some(expected) && lowercase.all(|c| name_chars.next() == Some(c)),
+            None => return c.is_whitespace().then(|| args[index..].trim()).filter(|rest| !rest.is_empty()),
+        };
+        if !matched {
+            return None;
+        }
+    }
+    None
+}
+
+/**
+ * Flow has a single room everyone is in.
+ */
+async fn join(_args: String, _json: Value, _ctx: Context) -> CommandResult {
+    Err(String::from("Rooms aren't supported (Everyone is in the same room)"))
+}
+
+/**
+ * Show the topic, or change it for everyone.
+ * { type: "topic", topic: "topic", by: "name" }
+ */
+async fn topic(args: String, _json: Value, ctx: Context) -> CommandResult {
+    if args.is_empty() {
+        return Ok(Some(match unsafe { &topic::TOPIC } {
+            Some((topic, by)) => format!("{} (set by {})", topic, by),
+            None => String::from("There is no topic"),
+        }));
+    }
+
+    let user = get_user(ctx.addr);
+    if !user.role.can(Permission::Moderate) {
+        return Err(format!("Access declined ({} can't change the topic)", user.role));
+    }
+
+    // A single dash clears the topic:
+    topic::set(if args == "-" { "" } else { &args }, &user.name);
+
+    let mut topic_json = topic::json();
+    topic_json["type"] = json!("topic");
+    send_all(SERVER_ADDR, topic_json.to_string()).await;
+
+    Ok(None)
+}
+
+async fn kick(args: String, _json: Value, ctx: Context) -> CommandResult {
+    let user = find_user(&args).ok_or("Usage: /kick <name> (User not found)")?;
+    if user.addr == ctx.addr {
+        return Err(String::from("You can't kick yourself"));
+    }
+
+    // The user is gone after the kick:
+    let (id, name) = (user.id.clone(), user.name.clone());
+    admin::kick(&id).await?;
+    Ok(Some(format!("Kicked {}", name)))
+}
+
+async fn ban(args: String, _json: Value, ctx: Context) -> CommandResult {
+    if args.is_empty() {
+        return Err(String::from("Usage: /ban <name|ip>"));
+    }
+
+    let user = get_user(ctx.addr);
+    let bans_self = match Ban::parse(&args) {
+        Ban::Ip(ip) => ctx.addr.ip() == ip,
+        Ban::Name(name) => names::skeleton(&user.name) == names::skeleton(&name),
+    };
+    if bans_self {
+        return Err(String::from("You can't ban yourself"));
+    }
+    Ok(Some(admin::ban(&args).await?.trim().to_string()))
+}
+
+async fn announce(args: String, _json: Value, _ctx: Context) -> CommandResult {
+    if args.is_empty() {
+        return Err(String::from("Usage: /announce <message>"));
+    }
+    admin::broadcast(&args).await?;
+    Ok(None)
+}
+
+async fn role(args: String, _json: Value, _ctx: Context) -> CommandResult {
+    admin::role(&args).await?;
+    Ok(Some(String::from("Role changed")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, strip_name, Input};
+
+    fn command(name: &str, args: &str) -> Input {
+        Input::Command(name.to_string(), args.to_string())
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(parse("/who"), command("who", ""));
+        assert_eq!(parse("/NICK  New Name "), command("nick", "New Name"));
+        assert_eq!(parse("/msg\tbob hi"), command("msg", "bob hi"));
+        assert_eq!(parse("/"), command("", ""));
+    }
+
+    #[test]
+    fn parse_chat() {
+        assert_eq!(parse("hello /who"), Input::Chat);
+        assert_eq!(parse(" /who"), Input::Chat);
+        assert_eq!(parse(""), Input::Chat);
+        assert_eq!(parse("//who"), Input::Escaped(String::from("/who")));
+        assert_eq!(parse("///"), Input::Escaped(String::from("//")));
+    }
+
+    #[test]
+    fn strip_name_ignores_case() {
+        assert_eq!(strip_name("Alice hello there", "alice"), Some("hello there"));
+        assert_eq!(strip_name("ALICE  hi", "Alice"), Some("hi"));
+    }
+
+    #[test]
+    fn strip_name_with_spaces() {
+        assert_eq!(strip_name("Mary Ann hi", "Mary Ann"), Some("hi"));
+        assert_eq!(strip_name("Mary Ann hi", "Mary"), Some("Ann hi"));
+    }
+
+    #[test]
+    fn strip_name_needs_a_message() {
+        assert_eq!(strip_name("alice", "alice"), None);
+        assert_eq!(strip_name("alice   ", "alice"), None);
+        assert_eq!(strip_name("alicex hi", "alice"), None);
+        assert_eq!(strip_name("bob hi", "alice"), None);
+    }
+
+    #[test]
+    fn strip_name_multibyte() {
+        // The Kelvin sign lowercases to a one byte "k", slicing by the lowercase length would panic:
+        assert_eq!(strip_name("\u{212A}\u{212A} hi", "kk"), Some("hi"));
+        assert_eq!(strip_name("\u{212A}elvin hi", "kelvin"), Some("hi"));
+        assert_eq!(strip_name("İ hi", "i\u{307}"), Some("hi"));
+        assert_eq!(strip_name("\u{212A}", "k"), None);
+    }
+}
@@ -0,0 +1,47 @@
+use std::sync::OnceLock;
+
+use tokio::sync::broadcast;
+
+/**
+ * How many events a subscriber can fall behind before it misses some.
+ */
+pub const EVENT_CAPACITY: usize = 1024;
+
+/**
+ * Things that happen on the server, users are referred to by id.
+ */
+#[derive(Debug, Clone)]
+pub enum Event {
+    UserJoined { id: String, name: String },
+    UserLeft { id: String, name: String },
+    UserRenamed { id: String, old: String, name: String },
+    ProfileUpdated { id: String },
+    ChatPosted { id: u64, sender: String, target: Option<String>, parent: Option<u64>, content: String },
+    ChatEdited { id: u64, by: String, content: String },
+    ChatDeleted { id: u64, by: String },
+    FileShared { id: u64, sender: String, name: String },
+    OfferCreated { id: String, origin: String, target: String },
+    OfferResolved { id: String, accepted: bool },
+    SessionEstablished { offer: String, origin: String, target: String },
+}
+
+static BUS: OnceLock<broadcast::Sender<Event>> = OnceLock::new();
+
+fn bus() -> &'static broadcast::Sender<Event> {
+    BUS.get_or_init(|| broadcast::channel(EVENT_CAPACITY).0)
+}
+
+/**
+ * Get every event from now on, a subscriber that falls too far behind gets a lagged error.
+ */
+pub fn subscribe() -> broadcast::Receiver<Event> {
+    bus().subscribe()
+}
+
+/**
+ * Let the subscribers know something happened, this never waits for them.
+ */
+pub fn emit(event: Event) {
+    // Nobody listening is fine.
+    let _ = bus().send(event);
+}
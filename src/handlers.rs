@@ -0,0 +1,119 @@
+use std::{collections::HashMap, future::Future, net::SocketAddr, pin::Pin};
+
+use serde_json::Value;
+
+use crate::{roles::Role, trafic, Socket, USERS};
+
+/**
+ * Who send a message and where to reach them.
+ */
+#[derive(Clone)]
+pub struct Context {
+    pub addr: SocketAddr,
+    pub socket: Socket,
+}
+
+/**
+ * The user who send a message, as handlers outside of flow get to see them.
+ */
+#[derive(Debug, Clone)]
+pub struct UserInfo {
+    pub id: String,
+    pub name: String,
+    pub role: Role,
+}
+
+impl Context {
+    /**
+     * Get the user who send the message, None if they haven't logged in yet.
+     */
+    pub fn user(&self) -> Option<UserInfo> {
+        unsafe {
+            USERS.iter()
+                .find(|user| user.addr == self.addr)
+                .map(|user| UserInfo { id: user.id.clone(), name: user.name.clone(), role: user.role })
+        }
+    }
+}
+
+/**
+ * Resolves to an error message if the message couldn't be handled.
+ */
+pub type HandlerFuture = Pin<Box<dyn Future<Output = Option<String>> + Send>>;
+
+/**
+ * Handles every message of one type.
+ * Async functions and closures taking the message and its context are handlers already.
+ */
+pub trait MessageHandler: Send + Sync {
+    fn handle(&self, json: Value, ctx: Context) -> HandlerFuture;
+}
+
+impl<F, Fut> MessageHandler for F
+where
+    F: Fn(Value, Context) -> Fut + Send + Sync,
+    Fut: Future<Output = Option<String>> + Send + 'static,
+{
+    fn handle(&self, json: Value, ctx: Context) -> HandlerFuture {
+        Box::pin(self(json, ctx))
+    }
+}
+
+/**
+ * The message handlers by the type of message they handle.
+ */
+#[derive(Default)]
+pub struct Registry {
+    handlers: HashMap<String, Box<dyn MessageHandler>>,
+}
+
+impl Registry {
+    /**
+     * A registry with the handlers of all message types flow knows about.
+     */
+    pub fn builtin() -> Registry {
+        let mut registry = Registry::default();
+
+        registry.register("hello", |json, ctx: Context| trafic::hello(json, ctx.addr, ctx.socket));
+        registry.register("login", |json, ctx: Context| trafic::login(json, ctx.addr, ctx.socket));
+        registry.register("resume", |json, ctx: Context| trafic::resume(json, ctx.addr, ctx.socket));
+        registry.register("presence", |json, ctx: Context| trafic::presence(json, ctx.addr));
+        registry.register("typing", |json, ctx: Context| trafic::typing(json, ctx.addr));
+        registry.register("rename", |json, ctx: Context| trafic::rename(json, ctx.addr));
+        registry.register("profile", |json, ctx: Context| trafic::profile(json, ctx.addr));
+        registry.register("chat", |json, ctx: Context| trafic::chat(json, ctx.addr));
+        registry.register("edit", |json, ctx: Context| trafic::edit(json, ctx.addr));
+        registry.register("delete", |json, ctx: Context| trafic::delete(json, ctx.addr));
+        registry.register("react", |json, ctx: Context| trafic::react(json, ctx.addr));
+        registry.register("read", |json, ctx: Context| trafic::read(json, ctx.addr));
+        registry.register("history", |json, ctx: Context| trafic::history(json, ctx.addr));
+        registry.register("file", |json, ctx: Context| trafic::file(json, ctx.addr));
+        registry.register("request", |json, ctx: Context| trafic::request(json, ctx.addr));    // Request for p2p
+        registry.register("offer", |json, ctx: Context| trafic::offer(json, ctx.addr));        // P2P offer
+        registry.register("session", |json, ctx: Context| trafic::session(json, ctx.addr));    // P2P session info
+        registry.register("block", |json, ctx: Context| trafic::block(json, ctx.addr));
+
+        registry
+    }
+
+    /**
+     * Register the handler of a message type, this replaces the handler it had before.
+     */
+    pub fn register(&mut self, msg_type: &str, handler: impl MessageHandler + 'static) {
+        self.handlers.insert(String::from(msg_type), Box::new(handler));
+    }
+
+    /**
+     * Take the handler of a message type out of the registry.
+     */
+    pub fn remove(&mut self, msg_type: &str) -> Option<Box<dyn MessageHandler>> {
+        self.handlers.remove(msg_type)
+    }
+
+    /**
+     * Get the handler of a message type.
+     */
+    pub fn get(&self, msg_type: &str) -> Option<&dyn MessageHandler> {
+        self.handlers.get(msg_type).map(|handler| handler.as_ref())
+    }
+}
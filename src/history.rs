@@ -0,0 +1,294 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde_json::{json, Value};
+
+use crate::{blocks, messages, store};
+
+/**
+ * How many chat messages are kept, older ones are dropped first.
+ */
+pub const MAX_HISTORY: usize = 1000;
+
+/**
+ * Most messages a client can get in one history request.
+ */
+pub const MAX_REPLAY: usize = 100;
+
+/**
+ * Most different reactions a single message can have.
+ */
+pub const MAX_REACTIONS: usize = 20;
+
+/**
+ * Chat messages as they were broadcast, with their edit log.
+ * Direct messages have a target and are only visible to their sender and target.
+ */
+pub static mut HISTORY: Vec<Value> = Vec::new();
+static DIRTY: AtomicBool = AtomicBool::new(false);
+
+/**
+ * Load the history from the store and continue the message ids after it.
+ */
+pub fn load() {
+    let Some(Value::Array(history)) = store::load("history") else { return };
+
+    let last_id = history.iter().filter_map(|entry| entry["id"].as_u64()).max().unwrap_or(0);
+    messages::NEXT_ID.fetch_max(last_id + 1, Ordering::Relaxed);
+
+    unsafe { HISTORY = history; }
+}
+
+/**
+ * Write the history to the store if it changed.
+ */
+pub fn flush() {
+    if DIRTY.swap(false, Ordering::Relaxed) {
+        unsafe { store::save("history", &Value::Array(HISTORY.clone())); }
+    }
+}
+
+/**
+ * Add a broadcast chat message to the history.
+ */
+pub fn push(msg_json: &Value) {
+    unsafe {
+        if HISTORY.len() >= MAX_HISTORY {
+            HISTORY.remove(0);
+        }
+
+        let mut entry = msg_json.clone();
+        entry["edits"] = json!([]);
+        entry["reactions"] = json!({});
+        HISTORY.push(entry);
+    }
+    DIRTY.store(true, Ordering::Relaxed);
+}
+
+/**
+ * Get a message from the history by id.
+ */
+pub fn get<'a>(id: u64) -> Option<&'a mut Value> {
+    unsafe { HISTORY.iter_mut().find(|entry| entry["id"].as_u64() == Some(id)) }
+}
+
+/**
+ * Check if a user is allowed to see a message.
+ */
+pub fn visible(entry: &Value, user: &str) -> bool {
+    entry["target"] == Value::Null || entry["target"] == user || entry["sender"]["id"] == user
+}
+
+/**
+ * Get the conversation a message belongs to from the point of view of a user,
+ * this is "public" or the id of the other user in a direct message.
+ */
+pub fn conversation(entry: &Value, user: &str) -> String {
+    match entry["target"].as_str() {
+        None => String::from("public"),
+        Some(target) if target != user => target.to_string(),
+        Some(_) => entry["sender"]["id"].as_str().unwrap_or_default().to_string(),
+    }
+}
+
+/**
+ * Replace the content of a message, the old content goes into its edit log.
+ * Deleted messages stay deleted.
+ */
+pub fn edit(id: u64, content: String, by: &str, timestamp: u64) -> Option<u64> {
+    let entry = get(id).filter(|entry| entry["deleted"] == Value::Null)?;
+
+    let previous = entry["content"].take();
+    entry["edits"].as_array_mut()?.push(json!({
+        "timestamp": timestamp,
+        "by": by,
+        "content": previous
+    }));
+    entry["content"] = json!(content);
+    entry["edited"] = json!(timestamp);
+
+    DIRTY.store(true, Ordering::Relaxed);
+    Some(timestamp)
+}
+
+/**
+ * Delete the content of a message, it stays in the edit log.
+ */
+pub fn delete(id: u64, by: &str, timestamp: u64) -> Option<u64> {
+    let entry = get(id)?;
+
+    let previous = entry["content"].take();
+    entry["edits"].as_array_mut()?.push(json!({
+        "timestamp": timestamp,
+        "by": by,
+        "content": previous
+    }));
+    entry["deleted"] = json!(timestamp);
+
+    DIRTY.store(true, Ordering::Relaxed);
+    Some(timestamp)
+}
+
+/**
+ * Add or remove the reaction of a user to a message, returns how many users reacted with it.
+ */
+pub fn react(id: u64, emoji: &str, user_id: &str, remove: bool) -> Result<usize, String> {
+    let entry = get(id).ok_or("Message not found")?;
+    let reactions = entry["reactions"].as_object_mut().ok_or("Message can't have reactions")?;
+
+    if !remove && !reactions.contains_key(emoji) && reactions.len() >= MAX_REACTIONS {
+        return Err(format!("Message already has {} different reactions", MAX_REACTIONS));
+    }
+
+    let users = reactions.entry(emoji).or_insert(json!([]));
+    let users = users.as_array_mut().ok_or("Message can't have reactions")?;
+
+    users.retain(|other| other != user_id);
+    if !remove {
+        users.push(json!(user_id));
+    }
+    let count = users.len();
+
+    if count == 0 {
+        reactions.remove(emoji);
+    }
+
+    DIRTY.store(true, Ordering::Relaxed);
+    Ok(count)
+}
+
+/**
+ * Strip the edit log from a message before sending it to clients,
+ * reactions are send as the number of users per emoji.
+ */
+pub fn public_json(entry: &Value) -> Value {
+    let mut entry = entry.clone();
+    if let Some(entry) = entry.as_object_mut() {
+        entry.remove("edits");
+
+        if let Some(Value::Object(reactions)) = entry.get_mut("reactions") {
+            for users in reactions.values_mut() {
+                *users = json!(users.as_array().map_or(0, |users| users.len()));
+            }
+        }
+    }
+    entry
+}
+
+/**
+ * Get the most recent messages a user can see before an id, oldest first.
+ * With a parent only the replies to that message are included, messages the viewer hides are left out.
+ */
+pub fn replay(viewer: &str, before: Option<u64>, parent: Option<u64>, limit: usize) -> Vec<Value> {
+    unsafe {
+        let mut messages: Vec<Value> = HISTORY.iter()
+            .rev()
+            .filter(|entry| visible(entry, viewer))
+            .filter(|entry| !blocks::hides(viewer, entry))
+            .filter(|entry| parent.is_none() || entry["parent"].as_u64() == parent)
+            .filter(|entry| before.is_none_or(|before| entry["id"].as_u64().is_some_and(|id| id < before)))
+            .take(limit.min(MAX_REPLAY))
+            .map(public_json)
+            .collect();
+        messages.reverse();
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use serial_test::serial;
+
+    use super::{delete, edit, get, push, react, replay, HISTORY, MAX_REACTIONS};
+    use crate::blocks::{self, BLOCKS};
+
+    fn chat(id: u64, sender: &str, target: Option<&str>) -> Value {
+        json!({ "type": "chat", "id": id, "sender": { "id": sender, "name": sender }, "target": target, "content": format!("message {}", id) })
+    }
+
+    #[test]
+    #[serial]
+    fn edits_keep_a_log() {
+        unsafe { HISTORY.clear(); }
+        push(&chat(1, "alice", None));
+
+        assert_eq!(edit(1, String::from("first"), "alice", 10), Some(10));
+        assert_eq!(edit(1, String::from("second"), "alice", 20), Some(20));
+        assert_eq!(edit(2, String::from("missing"), "alice", 30), None);
+
+        let entry = get(1).unwrap();
+        assert_eq!(entry["content"], "second");
+        assert_eq!(entry["edited"], 20);
+        assert_eq!(entry["edits"][0]["content"], "message 1");
+        assert_eq!(entry["edits"][1]["content"], "first");
+    }
+
+    #[test]
+    #[serial]
+    fn deleted_messages_stay_deleted() {
+        unsafe { HISTORY.clear(); }
+        push(&chat(1, "alice", None));
+
+        assert_eq!(delete(1, "alice", 10), Some(10));
+        assert_eq!(edit(1, String::from("back"), "alice", 20), None);
+
+        let entry = get(1).unwrap();
+        assert_eq!(entry["content"], Value::Null);
+        assert_eq!(entry["deleted"], 10);
+        assert_eq!(entry["edits"][0]["content"], "message 1");
+        assert_eq!(super::public_json(entry)["edits"], Value::Null);
+    }
+
+    #[test]
+    #[serial]
+    fn reactions_count_users_once() {
+        unsafe { HISTORY.clear(); }
+        push(&chat(1, "alice", None));
+
+        assert_eq!(react(1, "👍", "alice", false), Ok(1));
+        assert_eq!(react(1, "👍", "alice", false), Ok(1));
+        assert_eq!(react(1, "👍", "bob", false), Ok(2));
+        assert_eq!(super::public_json(get(1).unwrap())["reactions"], json!({ "👍": 2 }));
+
+        assert_eq!(react(1, "👍", "alice", true), Ok(1));
+        assert_eq!(react(1, "👍", "bob", true), Ok(0));
+        assert_eq!(get(1).unwrap()["reactions"], json!({}));
+        assert!(react(2, "👍", "alice", false).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn reactions_are_capped() {
+        unsafe { HISTORY.clear(); }
+        push(&chat(1, "alice", None));
+
+        for emoji in 0..MAX_REACTIONS {
+            react(1, &emoji.to_string(), "alice", false).unwrap();
+        }
+        assert!(react(1, "new", "bob", false).is_err());
+        assert_eq!(react(1, "0", "bob", false), Ok(2));
+        assert_eq!(react(1, "new", "bob", true), Ok(0));
+    }
+
+    #[test]
+    #[serial]
+    fn replay_shows_what_the_viewer_may_see() {
+        unsafe {
+            HISTORY.clear();
+            BLOCKS.clear();
+        }
+        push(&chat(1, "alice", None));
+        push(&chat(2, "alice", Some("bob")));
+        push(&chat(3, "alice", Some("carol")));
+        push(&json!({ "type": "chat", "id": 4, "sender": { "id": "bob" }, "target": null, "parent": 1 }));
+
+        let ids = |messages: Vec<Value>| messages.iter().map(|entry| entry["id"].as_u64().unwrap()).collect::<Vec<_>>();
+        assert_eq!(ids(replay("bob", None, None, 10)), [1, 2, 4]);
+        assert_eq!(ids(replay("bob", Some(4), None, 10)), [1, 2]);
+        assert_eq!(ids(replay("bob", None, None, 2)), [2, 4]);
+        assert_eq!(ids(replay("carol", None, Some(1), 10)), [4]);
+
+        blocks::block("carol", "bob", true).unwrap();
+        assert!(replay("carol", None, Some(1), 10).is_empty());
+    }
+}
@@ -1,6 +1,7 @@
 use std::net::SocketAddr;
 
 use colored::*;
+use serde_json::{json, Value};
 
 use crate::{USERS, FluxUser, OFFERS};
 
@@ -21,7 +22,6 @@ pub fn user_info(addr: SocketAddr, details: String, color: colored::Color) {
         match index {
             Some(i) => {
                 info!("{}{}{} {}", "[".bright_black(), USERS.get(i).expect("Can get username in user_info method").name.clone().color(color), "]".bright_black(), details);
-                ()
             },
             None => info!("{}{}{} {}", "[".bright_black(), addr.to_string().color(color), "]".bright_black(), details),
         }
@@ -38,9 +38,8 @@ pub fn user_err(addr: SocketAddr, details: String) {
         match index {
             Some(i) => {
                 info!("{}{}{} {}{}{} {}", "[".bright_black(), "ERR".bright_red(), "]".bright_black(), "[".bright_black(), USERS.get(i).expect("Can get username in user_info method").name.clone(), "]".bright_black(), details);
-                ()
             },
-            None => info!("{}{}{} {}{}{} {}", "[".bright_black(), "ERR".bright_red(), "]".bright_black(), "[".bright_black(), addr.to_string(), "]".bright_black(), details),
+            None => info!("{}{}{} {}{}{} {}", "[".bright_black(), "ERR".bright_red(), "]".bright_black(), "[".bright_black(), addr, "]".bright_black(), details),
         }
     }
 }
@@ -65,7 +64,7 @@ pub fn get_user<'a>(addr: SocketAddr) -> &'a FluxUser {
  * Get a user based on their id.
  */
 pub fn get_user_id<'a>(id: String) -> Option<&'a FluxUser> {
-    if user_id_exists(id.clone()) == false {
+    if !user_id_exists(id.clone()) {
         return None;
     }
 
@@ -74,7 +73,7 @@ pub fn get_user_id<'a>(id: String) -> Option<&'a FluxUser> {
 
         match index {
             Some(i) => {
-                return Some(USERS.get(i).unwrap());
+                Some(USERS.get(i).unwrap())
             },
             None => panic!("Tried to get user who doesn't exist"),
         }
@@ -87,14 +86,14 @@ pub fn get_user_id<'a>(id: String) -> Option<&'a FluxUser> {
 pub fn user_exists(addr: SocketAddr) -> bool {
     unsafe {
         let result = USERS.iter().any(|user| user.addr == addr);
-        if result == false {
+        if !result {
             user_info(
                 addr,
                 String::from("Unauthorized (Not logged in)"),
                 Color::Red
             );
         }
-        return result;
+        result
     }
 }
 
@@ -103,7 +102,7 @@ pub fn user_exists(addr: SocketAddr) -> bool {
  */
 pub fn user_id_exists(id: String) -> bool {
     unsafe {
-        return USERS.iter().any(|user| user.id == id);
+        USERS.iter().any(|user| user.id == id)
     }
 }
 
@@ -121,4 +120,21 @@ pub fn dispose_offer(id: String) {
             None => panic!("Tried to dispose of offer which doesn't exist"),
         }
     }
-}
\ No newline at end of file
+}
+
+/**
+ * List all users except the one with the given address, as they are send to clients.
+ */
+pub fn users_json(except: SocketAddr) -> Vec<Value> {
+    unsafe {
+        USERS.iter()
+            .filter(|user| user.addr != except)
+            .map(|user| json!({
+                "id": user.id,
+                "name": user.name,
+                "bot": user.bot,
+                "presence": user.presence.public_json()
+            }))
+            .collect()
+    }
+}
@@ -0,0 +1,280 @@
+#![allow(static_mut_refs)]
+
+use std::{net::SocketAddr, sync::{Arc, atomic::{AtomicBool, Ordering}}, time::{Duration, Instant}};
+
+#[macro_use]
+extern crate log;
+
+use futures_util::{StreamExt, stream::SplitSink};
+use send::{close_socket, send_all, send_socket};
+use serde_json::{Value, json};
+use tokio::{net::TcpStream, sync::Mutex};
+use tokio_tungstenite::{WebSocketStream, tungstenite::{Message, protocol::{WebSocketConfig, frame::coding::CloseCode}}};
+
+use colored::*;
+
+pub use commands::{ChatCommand, CommandResult, Commands};
+pub use events::Event;
+pub use handlers::{Context, HandlerFuture, MessageHandler, UserInfo};
+pub use middleware::{Middleware, Scope};
+pub use roles::{Permission, Role};
+pub use server::{Server, ServerBuilder};
+
+mod accounts;
+mod admin;
+mod bans;
+mod blocks;
+pub mod commands;
+pub mod events;
+pub mod handlers;
+mod history;
+mod info;
+mod messages;
+mod metrics;
+pub mod middleware;
+mod names;
+mod offline;
+mod presence;
+mod profiles;
+mod protocol;
+mod reads;
+mod resume;
+mod roles;
+#[cfg(feature = "scripting")]
+mod scripting;
+mod trafic;
+pub mod send;
+pub mod server;
+mod shutdown;
+mod store;
+mod topic;
+mod utils;
+
+pub static mut USERS: Vec<FluxUser> = Vec::new();
+pub static mut OFFERS: Vec<Offer> = Vec::new();
+pub static mut CLOSING: Vec<SocketAddr> = Vec::new();
+
+/**
+ * Every open websocket, also the ones that didn't login yet.
+ */
+pub static mut SOCKETS: Vec<(SocketAddr, Socket)> = Vec::new();
+
+/**
+ * The write half of a client's websocket.
+ */
+pub type Socket = Arc<Mutex<SplitSink<WebSocketStream<TcpStream>, Message>>>;
+
+/**
+ * How often the persistent stores are written to disk.
+ */
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub struct Offer {
+    origin: String,
+    target: String,
+    id: String
+}
+
+#[derive(Debug)]
+pub struct FluxUser {
+    id: String,
+    name: String,
+    addr: SocketAddr,
+    socket: Socket,
+    token: String,
+    role: roles::Role,
+    bot: bool,
+    reconnecting: Option<Instant>,
+    queue: Vec<String>,
+    presence: presence::Presence,
+    typing: Option<(Instant, Option<String>)>,
+    sent: Vec<messages::Stamp>
+}
+
+/**
+ * Write the changes of the persistent stores to disk, bans and accounts are written right away.
+ */
+fn flush_stores() {
+    history::flush();
+    reads::flush();
+    offline::flush();
+    blocks::flush();
+    profiles::flush();
+}
+
+/**
+ * Called when a new connection is made to the server.
+ */
+async fn accept_connection(stream: TcpStream, server: Arc<Server>) {
+    let addr = stream.peer_addr().expect("Failed to get user public ip");
+
+    info::info("Connection".blue(), addr.to_string());
+
+    // Refuse banned addresses before the handshake.
+    if bans::is_banned_ip(addr.ip()) {
+        info::info("Banned".red(), addr.to_string());
+        return;
+    }
+
+    // Make sure no reconnecting user is still holding on to this address.
+    resume::release(addr).await;
+
+    // Perform the websocket handshake.
+    let config = WebSocketConfig::default().max_message_size(Some(protocol::MAX_MESSAGE_SIZE));
+    let ws_stream = tokio_tungstenite::accept_async_with_config(stream, Some(config))
+        .await
+        .expect("Error during the websocket handshake occurred");
+
+    info::info("Handshaked".green(), addr.to_string());
+
+    // Split the streams write and read.
+    let (write, read) = ws_stream.split();
+    let writer = Arc::new(Mutex::new(write));
+
+    // Connections that finish the handshake during shutdown are too late.
+    if shutdown::started() {
+        close_socket(&writer, CloseCode::Away, "Server shutting down").await;
+        return;
+    }
+    unsafe { SOCKETS.push((addr, Arc::clone(&writer))); }
+
+    // Read incoming messages and process them:
+    let closed = AtomicBool::new(false);
+    read.for_each(|message| async {
+        match message {
+            Ok(msg) => {
+                // Remember if the user closed the connection on purpose.
+                if msg.is_close() {
+                    closed.store(true, Ordering::Relaxed);
+                }
+
+                // Ignore users who are being disconnected.
+                if unsafe { CLOSING.contains(&addr) } {
+                    return;
+                }
+
+                let writer_cl = Arc::clone(&writer);
+
+                // Check if the message isn't empty or a close frame.
+                if !msg.is_empty() && !msg.is_close() {
+                    metrics::add(&metrics::BYTES_IN, msg.len());
+                    let data = serde_json::from_str::<Value>(msg.to_string().as_str());
+
+                    // Check if the message is valid JSON:
+                    match data {
+                        Ok(json) => validate_json(json, addr, writer_cl, &server).await,
+                        Err(_) => info::user_err(
+                            addr,
+                            String::from("Json -> Invalid message format")
+                        ),
+                    };
+                }
+            },
+            Err(err) => info::user_err(addr, format!("Socket -> {}", err)),
+        }
+    }).await;
+
+    // Handle user disconnect, if the connection dropped they get some time to resume their session:
+    if closed.load(Ordering::Relaxed) || !protocol::has_feature(addr, "resume") {
+        remove_user(addr).await;
+    } else {
+        resume::suspend(addr).await;
+    }
+    unsafe {
+        CLOSING.retain(|closing| *closing != addr);
+        SOCKETS.retain(|(socket_addr, _)| *socket_addr != addr);
+    }
+    protocol::forget(addr);
+}
+
+/**
+ * Remove a user by their socket address.
+ */
+async fn remove_user(addr: SocketAddr) {
+    unsafe {
+        let index = USERS.iter().position(|user| user.addr == addr);
+
+        match index {
+            Some(i) => {
+                info::info("Disconnected".red(), String::clone(&USERS.get(i).expect("Can get user when disconnected").name));
+
+                // Send an update to all other users that a user has left:
+                let user = USERS.get(i).expect("Can read user");
+                let update_json = json!({
+                    "type": "leave",
+                    "user": {
+                        "id": user.id,
+                        "name": user.name
+                    }
+                });
+
+                send_all(addr, update_json.to_string()).await;
+                events::emit(Event::UserLeft { id: user.id.clone(), name: user.name.clone() });
+
+                // Cancel any connected offers:
+                let offers = OFFERS.len();
+                OFFERS.retain(|offer| offer.origin != user.id && offer.target != user.id);
+                metrics::add(&metrics::OFFERS_EXPIRED, offers - OFFERS.len());
+
+                // Guests don't come back with the same id:
+                if accounts::get(&user.id).is_none() {
+                    blocks::forget(&user.id);
+                }
+
+                USERS.remove(i);
+            },
+            None => info::info("Hard Disconnect".red(), addr.to_string()),
+        }
+    }
+}
+
+/**
+ * Validates the json message and hands it to the handler registered for its type.
+ */
+async fn validate_json(json: Value, addr: SocketAddr, socket: Socket, server: &Server) {
+
+    // Check if type exists on the message:
+    match &json["type"] {
+        Value::String(msg_type) => {
+
+            // Scripts can handle the types flow doesn't know about:
+            let handler = server.handlers.get(msg_type);
+            #[cfg(feature = "scripting")]
+            let script = scripting::ScriptHandler::find(msg_type);
+            #[cfg(feature = "scripting")]
+            let handler = handler.or(script.as_ref().map(|script| script as &dyn MessageHandler));
+
+            // Pass the message through the middlewares to its handler, unknown types share one label:
+            let err = match handler {
+                Some(handler) => server.pipeline.run(msg_type, json.clone(), Context { addr, socket: socket.clone() }, handler).await,
+                None => {
+                    metrics::message("unknown");
+                    info::user_err(addr, format!("Json -> Unknown type \"{}\"", msg_type));
+                    Some(format!("Invalid (Unknown type \"{}\")", msg_type))
+                }
+            };
+
+            // Let the user know if there is an error:
+            if let Some(err) = err {
+                let error_json = json!({
+                    "type": "error",
+                    "source": msg_type,
+                    "message": err
+                });
+                send_socket(&socket, error_json.to_string()).await;
+
+                // Close the connection if the handler refused it.
+                if unsafe { CLOSING.contains(&addr) } {
+                    close_socket(&socket, CloseCode::Protocol, &err).await;
+                }
+            }
+        }
+
+        _ => info::user_err(
+            addr,
+            String::from("Json -> Missing type field")
+        ),
+    }
+
+}
\ No newline at end of file
@@ -0,0 +1,113 @@
+use std::{net::SocketAddr, sync::atomic::{AtomicU64, Ordering}, time::{SystemTime, UNIX_EPOCH}};
+
+use serde_json::{json, Value};
+
+use crate::{protocol, send::send_only, USERS};
+
+/**
+ * How many nonces are remembered per user to recognize retries.
+ */
+pub const MAX_NONCES: usize = 64;
+
+/**
+ * Longest nonce a client can attach to a message.
+ */
+pub const MAX_NONCE_LENGTH: usize = 64;
+
+pub static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/**
+ * The server assigned id and timestamp of a relayed message.
+ */
+#[derive(Debug, Clone)]
+pub struct Stamp {
+    pub nonce: Option<String>,
+    pub id: u64,
+    pub timestamp: u64,
+    pub duplicate: bool,
+}
+
+/**
+ * Milliseconds since the unix epoch.
+ */
+pub fn timestamp() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_millis() as u64)
+}
+
+/**
+ * Give a message from a user an id and timestamp.
+ * If the user already send a message with the same nonce, its stamp is returned as a duplicate.
+ */
+pub fn stamp(addr: SocketAddr, nonce: &Value) -> Result<Stamp, String> {
+    let nonce = match nonce {
+        Value::Null => None,
+        Value::String(nonce) => Some(nonce.clone()),
+        Value::Number(nonce) => Some(nonce.to_string()),
+        _ => return Err(String::from("Nonce must be a string or number")),
+    };
+
+    if nonce.as_ref().is_some_and(|nonce| nonce.len() > MAX_NONCE_LENGTH) {
+        return Err(format!("Nonce is longer than {} bytes", MAX_NONCE_LENGTH));
+    }
+
+    let user = unsafe { USERS.iter_mut().find(|user| user.addr == addr) };
+    let user = user.expect("Tried to stamp message of user who doesn't exist");
+
+    // Recognize retries by their nonce:
+    if let Some(nonce) = &nonce {
+        if let Some(sent) = user.sent.iter().find(|sent| sent.nonce.as_ref() == Some(nonce)) {
+            return Ok(Stamp { duplicate: true, ..sent.clone() });
+        }
+    }
+
+    let stamp = Stamp {
+        nonce,
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        timestamp: timestamp(),
+        duplicate: false,
+    };
+
+    if stamp.nonce.is_some() {
+        if user.sent.len() >= MAX_NONCES {
+            user.sent.remove(0);
+        }
+        user.sent.push(stamp.clone());
+    }
+
+    Ok(stamp)
+}
+
+/**
+ * Forget the nonce of a message that couldn't be relayed after all, so a retry isn't taken for a duplicate.
+ */
+pub fn unstamp(addr: SocketAddr, stamp: &Stamp) {
+    let Some(nonce) = &stamp.nonce else { return };
+
+    unsafe {
+        if let Some(user) = USERS.iter_mut().find(|user| user.addr == addr) {
+            user.sent.retain(|sent| sent.nonce.as_ref() != Some(nonce));
+        }
+    }
+}
+
+/**
+ * Let the sender know their message was relayed.
+ * { type: "ack", nonce: "client_nonce", id: 1, timestamp: 0, duplicate: false }
+ */
+pub async fn ack(addr: SocketAddr, stamp: &Stamp) {
+    if !protocol::has_feature(addr, "ack") {
+        return;
+    }
+
+    if let Some(nonce) = &stamp.nonce {
+        let ack_json = json!({
+            "type": "ack",
+            "nonce": nonce,
+            "id": stamp.id,
+            "timestamp": stamp.timestamp,
+            "duplicate": stamp.duplicate
+        });
+
+        send_only(addr, ack_json.to_string()).await;
+    }
+}
@@ -0,0 +1,141 @@
+use std::{collections::BTreeMap, fmt::Write, sync::{Mutex, atomic::{AtomicU64, Ordering}}, time::Duration};
+
+use colored::*;
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}};
+
+use crate::{info, OFFERS, USERS};
+
+pub static LOGINS: AtomicU64 = AtomicU64::new(0);
+pub static BYTES_IN: AtomicU64 = AtomicU64::new(0);
+pub static BYTES_OUT: AtomicU64 = AtomicU64::new(0);
+pub static OFFERS_ACCEPTED: AtomicU64 = AtomicU64::new(0);
+pub static OFFERS_DECLINED: AtomicU64 = AtomicU64::new(0);
+pub static OFFERS_EXPIRED: AtomicU64 = AtomicU64::new(0);
+pub static HANDLER_ERRORS: AtomicU64 = AtomicU64::new(0);
+
+static MESSAGES: Mutex<BTreeMap<String, u64>> = Mutex::new(BTreeMap::new());
+
+/**
+ * Upper bounds (in seconds) of the send latency histogram buckets.
+ */
+const LATENCY_BUCKETS: [f64; 8] = [0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0];
+static LATENCY_COUNTS: [AtomicU64; 8] = [const { AtomicU64::new(0) }; 8];
+static LATENCY_TOTAL: AtomicU64 = AtomicU64::new(0);
+static LATENCY_SUM_MICROS: AtomicU64 = AtomicU64::new(0);
+
+/**
+ * Increment one of the counters above.
+ */
+pub fn inc(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/**
+ * Add an amount to one of the counters above.
+ */
+pub fn add(counter: &AtomicU64, amount: usize) {
+    counter.fetch_add(amount as u64, Ordering::Relaxed);
+}
+
+/**
+ * Count a handled message by its type.
+ */
+pub fn message(msg_type: &str) {
+    let mut messages = MESSAGES.lock().unwrap();
+    *messages.entry(String::from(msg_type)).or_insert(0) += 1;
+}
+
+/**
+ * Record how long it took to send a message to a socket.
+ */
+pub fn send_latency(elapsed: Duration) {
+    let seconds = elapsed.as_secs_f64();
+    for (i, bound) in LATENCY_BUCKETS.iter().enumerate() {
+        if seconds <= *bound {
+            inc(&LATENCY_COUNTS[i]);
+        }
+    }
+    inc(&LATENCY_TOTAL);
+    LATENCY_SUM_MICROS.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+}
+
+/**
+ * Render all metrics in the Prometheus text format.
+ */
+pub fn render() -> String {
+    let mut out = String::new();
+    let (users, offers) = unsafe { (USERS.len(), OFFERS.len()) };
+
+    let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}\n{} {}", name, help, name, kind, name, value);
+    };
+
+    metric("flow_connected_users", "gauge", "Users currently logged in.", users as u64);
+    metric("flow_active_offers", "gauge", "P2P offers waiting for a session.", offers as u64);
+    metric("flow_logins_total", "counter", "Successful logins.", LOGINS.load(Ordering::Relaxed));
+    metric("flow_bytes_received_total", "counter", "Bytes received from clients.", BYTES_IN.load(Ordering::Relaxed));
+    metric("flow_bytes_sent_total", "counter", "Bytes sent to clients.", BYTES_OUT.load(Ordering::Relaxed));
+    metric("flow_offers_accepted_total", "counter", "P2P offers accepted by their target.", OFFERS_ACCEPTED.load(Ordering::Relaxed));
+    metric("flow_offers_declined_total", "counter", "P2P offers declined by their target.", OFFERS_DECLINED.load(Ordering::Relaxed));
+    metric("flow_offers_expired_total", "counter", "P2P offers dropped because a participant left.", OFFERS_EXPIRED.load(Ordering::Relaxed));
+    metric("flow_handler_errors_total", "counter", "Messages rejected by a handler.", HANDLER_ERRORS.load(Ordering::Relaxed));
+
+    let _ = writeln!(out, "# HELP flow_messages_total Messages received by type.\n# TYPE flow_messages_total counter");
+    for (msg_type, count) in MESSAGES.lock().unwrap().iter() {
+        let _ = writeln!(out, "flow_messages_total{{type=\"{}\"}} {}", msg_type, count);
+    }
+
+    let _ = writeln!(out, "# HELP flow_send_latency_seconds Time spent sending a message to a socket.\n# TYPE flow_send_latency_seconds histogram");
+    for (i, bound) in LATENCY_BUCKETS.iter().enumerate() {
+        let _ = writeln!(out, "flow_send_latency_seconds_bucket{{le=\"{}\"}} {}", bound, LATENCY_COUNTS[i].load(Ordering::Relaxed));
+    }
+    let total = LATENCY_TOTAL.load(Ordering::Relaxed);
+    let _ = writeln!(out, "flow_send_latency_seconds_bucket{{le=\"+Inf\"}} {}", total);
+    let _ = writeln!(out, "flow_send_latency_seconds_sum {}", LATENCY_SUM_MICROS.load(Ordering::Relaxed) as f64 / 1_000_000.0);
+    let _ = writeln!(out, "flow_send_latency_seconds_count {}", total);
+
+    out
+}
+
+/**
+ * Serve the metrics over http on the given address.
+ * Only `GET /metrics` is supported, everything else is a 404.
+ */
+pub async fn serve(addr: String) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            info::info("Metrics".red(), format!("Failed to bind to {} ({})", addr, err));
+            return;
+        }
+    };
+    info::info("Metrics".white(), format!("Serving on: http://{}/metrics", addr));
+
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(respond(stream));
+    }
+}
+
+/**
+ * Answer a single http request.
+ */
+async fn respond(mut stream: TcpStream) {
+    let mut buf = [0u8; 1024];
+    let len = match stream.read(&mut buf).await {
+        Ok(len) => len,
+        Err(_) => return,
+    };
+
+    let request = String::from_utf8_lossy(&buf[..len]);
+    let response = if request.starts_with("GET /metrics ") {
+        let body = render();
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(), body
+        )
+    } else {
+        String::from("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+    };
+
+    let _ = stream.write_all(response.as_bytes()).await;
+}
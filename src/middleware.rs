@@ -0,0 +1,513 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Mutex, time::Instant};
+
+use serde_json::{json, Value};
+
+use crate::{
+    handlers::{Context, MessageHandler},
+    info, metrics, protocol, roles,
+    utils::fuppercase,
+};
+
+/**
+ * Most bytes a message other than a file can be.
+ */
+pub const MAX_MESSAGE_LENGTH: usize = 64 << 10;
+
+/**
+ * How many messages a client can send in a burst, and how many per second after that.
+ */
+pub const RATE_BURST: f64 = 30.0;
+pub const RATE_PER_SECOND: f64 = 10.0;
+
+/**
+ * Wraps the handling of a message.
+ * An error from before stops the message from reaching its handler and is send to the user instead,
+ * after gets the final error of the message, if there was one.
+ */
+pub trait Middleware: Send + Sync {
+    fn before(&self, _msg_type: &str, _json: &mut Value, _ctx: &Context) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn after(&self, _msg_type: &str, _ctx: &Context, _err: Option<&str>) {}
+}
+
+/**
+ * Which message types a middleware runs for.
+ */
+pub enum Scope {
+    All,
+    Only(Vec<String>),
+    Except(Vec<String>),
+}
+
+impl Scope {
+    fn contains(&self, msg_type: &str) -> bool {
+        match self {
+            Scope::All => true,
+            Scope::Only(types) => types.iter().any(|only| only == msg_type),
+            Scope::Except(types) => !types.iter().any(|except| except == msg_type),
+        }
+    }
+}
+
+/**
+ * The middlewares a message goes through, in the order they were added.
+ */
+#[derive(Default)]
+pub struct Pipeline {
+    middlewares: Vec<(Scope, Box<dyn Middleware>)>,
+}
+
+impl Pipeline {
+    /**
+     * The middlewares every flow server runs.
+     */
+    pub fn builtin(rate_limit: RateLimit) -> Pipeline {
+        let mut pipeline = Pipeline::default();
+
+        pipeline.add(Scope::All, Logging);
+        pipeline.add(Scope::All, Metrics);
+        // Unauthorized messages count towards the rate limit too, so they can't flood the server:
+        pipeline.add(Scope::All, rate_limit);
+        pipeline.add(Scope::Except(types(&["hello", "login", "resume"])), Auth);
+        pipeline.add(Scope::All, Features);
+        pipeline.add(Scope::All, Permissions);
+        pipeline.add(Scope::Except(types(&["file"])), SizeLimit(MAX_MESSAGE_LENGTH));
+
+        pipeline
+    }
+
+    pub fn add(&mut self, scope: Scope, middleware: impl Middleware + 'static) {
+        self.middlewares.push((scope, Box::new(middleware)));
+    }
+
+    /**
+     * Add the middlewares of another pipeline after these.
+     */
+    pub fn append(&mut self, other: Pipeline) {
+        self.middlewares.extend(other.middlewares);
+    }
+
+    /**
+     * Run a message through the middlewares and its handler.
+     */
+    pub async fn run(&self, msg_type: &str, mut json: Value, ctx: Context, handler: &dyn MessageHandler) -> Option<String> {
+        let middlewares: Vec<&dyn Middleware> = self.middlewares.iter()
+            .filter(|(scope, _)| scope.contains(msg_type))
+            .map(|(_, middleware)| middleware.as_ref())
+            .collect();
+
+        // Stop at the first middleware that refuses the message:
+        let mut passed = 0;
+        let mut err = None;
+        for middleware in middlewares.iter() {
+            passed += 1;
+            if let Err(refused) = middleware.before(msg_type, &mut json, &ctx) {
+                err = Some(refused);
+                break;
+            }
+        }
+
+        if err.is_none() {
+            err = handler.handle(json, ctx.clone()).await;
+        }
+
+        for middleware in middlewares[..passed].iter().rev() {
+            middleware.after(msg_type, &ctx, err.as_deref());
+        }
+
+        err
+    }
+}
+
+/**
+ * Turn a list of message types into a scope list.
+ */
+pub fn types(types: &[&str]) -> Vec<String> {
+    types.iter().map(|msg_type| msg_type.to_string()).collect()
+}
+
+/**
+ * Logs the messages that went wrong.
+ */
+pub struct Logging;
+
+impl Middleware for Logging {
+    fn after(&self, msg_type: &str, ctx: &Context, err: Option<&str>) {
+        if let Some(err) = err {
+            info::user_err(ctx.addr, format!("{} -> {}", fuppercase(msg_type), err));
+        }
+    }
+}
+
+/**
+ * Counts the messages by type and the ones that went wrong.
+ */
+pub struct Metrics;
+
+impl Middleware for Metrics {
+    fn before(&self, msg_type: &str, _json: &mut Value, _ctx: &Context) -> Result<(), String> {
+        metrics::message(msg_type);
+        Ok(())
+    }
+
+    fn after(&self, _msg_type: &str, _ctx: &Context, err: Option<&str>) {
+        if err.is_some() {
+            metrics::inc(&metrics::HANDLER_ERRORS);
+        }
+    }
+}
+
+/**
+ * Only lets through messages of users that are logged in.
+ */
+pub struct Auth;
+
+impl Middleware for Auth {
+    fn before(&self, _msg_type: &str, _json: &mut Value, ctx: &Context) -> Result<(), String> {
+        match info::user_exists(ctx.addr) {
+            true => Ok(()),
+            false => Err(String::from("User not authorized")),
+        }
+    }
+}
+
+/**
+ * Only lets through messages of the features the client agreed on in its hello.
+ */
+pub struct Features;
+
+impl Middleware for Features {
+    fn before(&self, msg_type: &str, json: &mut Value, ctx: &Context) -> Result<(), String> {
+        match protocol::required_feature(msg_type, json) {
+            Some(feature) if !protocol::has_feature(ctx.addr, feature) => Err(format!("Feature \"{}\" wasn't negotiated", feature)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/**
+ * Checks the role of the user allows the message.
+ */
+pub struct Permissions;
+
+impl Middleware for Permissions {
+    fn before(&self, msg_type: &str, _json: &mut Value, ctx: &Context) -> Result<(), String> {
+        match roles::check(ctx.addr, msg_type) {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+/**
+ * Refuses messages longer than a number of bytes.
+ */
+pub struct SizeLimit(pub usize);
+
+impl Middleware for SizeLimit {
+    fn before(&self, _msg_type: &str, json: &mut Value, _ctx: &Context) -> Result<(), String> {
+        match json.to_string().len() > self.0 {
+            true => Err(format!("Message is longer than {} bytes", self.0)),
+            false => Ok(()),
+        }
+    }
+}
+
+/**
+ * The tokens left and when they were last counted, by client and the message type if it has its own limit.
+ */
+type Buckets = HashMap<(SocketAddr, Option<String>), (f64, Instant)>;
+
+/**
+ * Limits how fast a client can send messages, every client gets a bucket of tokens that slowly refills.
+ * Message types with their own limit get their own bucket, the others share one.
+ */
+pub struct RateLimit {
+    burst: f64,
+    per_second: f64,
+    limits: HashMap<String, (f64, f64)>,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimit {
+    pub fn new(burst: f64, per_second: f64) -> RateLimit {
+        RateLimit { burst, per_second, limits: HashMap::new(), buckets: Mutex::new(HashMap::new()) }
+    }
+
+    /**
+     * Give a message type its own burst and rate, it doesn't use up the tokens of the other types.
+     */
+    pub fn limit(mut self, msg_type: &str, burst: f64, per_second: f64) -> RateLimit {
+        self.limits.insert(msg_type.to_string(), (burst, per_second));
+        self
+    }
+
+    fn rate(&self, msg_type: Option<&String>) -> (f64, f64) {
+        msg_type.and_then(|msg_type| self.limits.get(msg_type)).copied().unwrap_or((self.burst, self.per_second))
+    }
+
+    /**
+     * Take a token from the bucket a message goes in, returns false if there are none left.
+     */
+    fn take(&self, addr: SocketAddr, msg_type: &str, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+
+        // Forget the buckets that are full again.
+        buckets.retain(|(_, msg_type), (_, last)| {
+            let (burst, per_second) = self.rate(msg_type.as_ref());
+            now.saturating_duration_since(*last).as_secs_f64() < burst / per_second
+        });
+
+        let msg_type = Some(msg_type.to_string()).filter(|msg_type| self.limits.contains_key(msg_type));
+        let (burst, per_second) = self.rate(msg_type.as_ref());
+
+        let (tokens, last) = buckets.entry((addr, msg_type)).or_insert((burst, now));
+        *tokens = (*tokens + now.saturating_duration_since(*last).as_secs_f64() * per_second).min(burst);
+        *last = now;
+
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+}
+
+impl Default for RateLimit {
+    /**
+     * Typing indicators are send while typing, they get their own bucket so they don't use up the one for chat.
+     */
+    fn default() -> RateLimit {
+        RateLimit::new(RATE_BURST, RATE_PER_SECOND).limit("typing", RATE_BURST, RATE_PER_SECOND)
+    }
+}
+
+impl Middleware for RateLimit {
+    fn before(&self, msg_type: &str, _json: &mut Value, ctx: &Context) -> Result<(), String> {
+        match self.take(ctx.addr, msg_type, Instant::now()) {
+            true => Ok(()),
+            false => Err(String::from("Slow down (Too many messages)")),
+        }
+    }
+}
+
+/**
+ * Masks words in the content of messages.
+ */
+pub struct ContentFilter {
+    words: Vec<String>,
+}
+
+impl ContentFilter {
+    pub fn new(words: &[&str]) -> ContentFilter {
+        ContentFilter { words: words.iter().map(|word| word.to_lowercase()).collect() }
+    }
+
+    /**
+     * Mask the words of the content that are on the list, whatever their case or the punctuation around them.
+     */
+    fn filter(&self, content: &str) -> String {
+        let filtered: Vec<String> = content.split(' ')
+            .map(|word| {
+                let bare = word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase();
+                match self.words.contains(&bare) {
+                    true => word.replace(|c: char| c.is_alphanumeric(), "*"),
+                    false => word.to_string(),
+                }
+            })
+            .collect();
+
+        filtered.join(" ")
+    }
+}
+
+impl Middleware for ContentFilter {
+    fn before(&self, _msg_type: &str, json: &mut Value, _ctx: &Context) -> Result<(), String> {
+        if let Some(content) = json["content"].as_str() {
+            json["content"] = json!(self.filter(content));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+    use futures_util::StreamExt;
+    use serde_json::{json, Value};
+    use tokio::net::TcpListener;
+
+    use super::{types, ContentFilter, Middleware, Pipeline, RateLimit, Scope, SizeLimit};
+    use crate::handlers::Context;
+
+    /**
+     * A context with a real websocket behind it, the other side is dropped.
+     */
+    async fn context() -> Context {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+
+        let client = tokio::spawn(tokio_tungstenite::connect_async(url));
+        let (stream, addr) = listener.accept().await.unwrap();
+        let ws_stream = tokio_tungstenite::accept_async(stream).await.unwrap();
+        client.await.unwrap().unwrap();
+
+        let (write, _) = ws_stream.split();
+        Context { addr, socket: Arc::new(tokio::sync::Mutex::new(write)) }
+    }
+
+    /**
+     * Writes down when it runs, and refuses messages if it's told to.
+     */
+    struct Recorder {
+        name: &'static str,
+        refuse: bool,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Middleware for Recorder {
+        fn before(&self, _msg_type: &str, json: &mut Value, _ctx: &Context) -> Result<(), String> {
+            self.log.lock().unwrap().push(format!("before {}", self.name));
+            json["seen"] = json!(self.name);
+            match self.refuse {
+                true => Err(format!("refused by {}", self.name)),
+                false => Ok(()),
+            }
+        }
+
+        fn after(&self, _msg_type: &str, _ctx: &Context, err: Option<&str>) {
+            self.log.lock().unwrap().push(format!("after {} {:?}", self.name, err));
+        }
+    }
+
+    fn pipeline(refuse: &str, log: &Arc<Mutex<Vec<String>>>) -> Pipeline {
+        let mut pipeline = Pipeline::default();
+        for name in ["a", "b", "c"] {
+            pipeline.add(Scope::All, Recorder { name, refuse: name == refuse, log: Arc::clone(log) });
+        }
+        pipeline
+    }
+
+    #[tokio::test]
+    async fn pipeline_runs_in_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let handler_log = Arc::clone(&log);
+        let handler = move |json: Value, _ctx: Context| {
+            handler_log.lock().unwrap().push(format!("handler {}", json["seen"]));
+            async { None }
+        };
+
+        let err = pipeline("", &log).run("chat", json!({}), context().await, &handler).await;
+        assert_eq!(err, None);
+        assert_eq!(*log.lock().unwrap(), [
+            "before a", "before b", "before c", "handler \"c\"", "after c None", "after b None", "after a None",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn pipeline_stops_at_a_refusal() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let handler_log = Arc::clone(&log);
+        let handler = move |_json: Value, _ctx: Context| {
+            handler_log.lock().unwrap().push(String::from("handler"));
+            async { None }
+        };
+
+        let err = pipeline("b", &log).run("chat", json!({}), context().await, &handler).await;
+        assert_eq!(err.as_deref(), Some("refused by b"));
+        assert_eq!(*log.lock().unwrap(), [
+            "before a", "before b", "after b Some(\"refused by b\")", "after a Some(\"refused by b\")",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn pipeline_skips_out_of_scope() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut pipeline = Pipeline::default();
+        pipeline.add(Scope::Only(types(&["file"])), Recorder { name: "only", refuse: true, log: Arc::clone(&log) });
+        pipeline.add(Scope::Except(types(&["chat"])), Recorder { name: "except", refuse: true, log: Arc::clone(&log) });
+
+        let handler = |_json: Value, _ctx: Context| async { Some(String::from("handler failed")) };
+        let err = pipeline.run("chat", json!({}), context().await, &handler).await;
+        assert_eq!(err.as_deref(), Some("handler failed"));
+        assert!(log.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn scopes() {
+        assert!(Scope::All.contains("chat"));
+        assert!(Scope::Only(types(&["chat", "file"])).contains("file"));
+        assert!(!Scope::Only(types(&["chat"])).contains("file"));
+        assert!(Scope::Except(types(&["hello", "login"])).contains("chat"));
+        assert!(!Scope::Except(types(&["hello", "login"])).contains("login"));
+        assert!(Scope::Except(Vec::new()).contains("login"));
+    }
+
+    #[test]
+    fn rate_limit_burst() {
+        let limit = RateLimit::new(3.0, 1.0);
+        let addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let other: SocketAddr = "127.0.0.1:2".parse().unwrap();
+        let now = Instant::now();
+
+        assert!((0..3).all(|_| limit.take(addr, "chat", now)));
+        assert!(!limit.take(addr, "chat", now));
+        assert!(!limit.take(addr, "file", now));
+        assert!(limit.take(other, "chat", now));
+    }
+
+    #[test]
+    fn rate_limit_refills() {
+        let limit = RateLimit::new(2.0, 4.0);
+        let addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let now = Instant::now();
+
+        assert!(limit.take(addr, "chat", now) && limit.take(addr, "chat", now));
+        assert!(!limit.take(addr, "chat", now + Duration::from_millis(100)));
+        assert!(limit.take(addr, "chat", now + Duration::from_millis(350)));
+        assert!(!limit.take(addr, "chat", now + Duration::from_millis(350)));
+
+        // Never more than the burst, even after a long time:
+        let later = now + Duration::from_secs(60);
+        assert!(limit.take(addr, "chat", later) && limit.take(addr, "chat", later));
+        assert!(!limit.take(addr, "chat", later));
+    }
+
+    #[test]
+    fn rate_limit_per_type() {
+        let limit = RateLimit::new(1.0, 1.0).limit("typing", 2.0, 1.0);
+        let addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let now = Instant::now();
+
+        assert!(limit.take(addr, "typing", now) && limit.take(addr, "typing", now));
+        assert!(!limit.take(addr, "typing", now));
+
+        // Typing has its own bucket, the other types share one:
+        assert!(limit.take(addr, "chat", now));
+        assert!(!limit.take(addr, "file", now));
+    }
+
+    #[tokio::test]
+    async fn size_limit() {
+        let ctx = context().await;
+        let limit = SizeLimit(20);
+
+        assert!(limit.before("chat", &mut json!({ "content": "short" }), &ctx).is_ok());
+        assert!(limit.before("chat", &mut json!({ "content": "a bit too long" }), &ctx).is_err());
+    }
+
+    #[test]
+    fn content_filter() {
+        let filter = ContentFilter::new(&["Darn", "heck"]);
+
+        assert_eq!(filter.filter("darn it"), "**** it");
+        assert_eq!(filter.filter("DARN, what the Heck?!"), "****, what the ****?!");
+        assert_eq!(filter.filter("\"heck\" ...heck..."), "\"****\" ...****...");
+        assert_eq!(filter.filter("darned hecks"), "darned hecks");
+        assert_eq!(filter.filter("  spaces  stay "), "  spaces  stay ");
+        assert_eq!(filter.filter(""), "");
+    }
+}
@@ -0,0 +1,191 @@
+use serde_json::Value;
+
+use crate::USERS;
+
+pub const MAX_LENGTH: usize = 32;
+
+/**
+ * Names nobody is allowed to use, compared by their skeleton.
+ */
+const RESERVED: [&str; 7] = ["server", "admin", "administrator", "moderator", "system", "flow", "root"];
+
+/**
+ * What to do when a name is already used by an online user.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NamePolicy {
+    Reject,
+    Suffix,
+}
+
+impl NamePolicy {
+    pub fn parse(policy: &str) -> Option<NamePolicy> {
+        match policy {
+            "reject" => Some(NamePolicy::Reject),
+            "suffix" => Some(NamePolicy::Suffix),
+            _ => None,
+        }
+    }
+}
+
+pub static mut NAME_POLICY: NamePolicy = NamePolicy::Reject;
+
+/**
+ * Trim a name and collapse its inner whitespace.
+ */
+pub fn normalize(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/**
+ * Reduce a name to the characters it looks like, so "ΑDMIN", "Adm1n" and "admin" all end up the same.
+ */
+pub fn skeleton(name: &str) -> String {
+    let mut skeleton = String::new();
+
+    for c in name.chars() {
+        // Fold fullwidth forms onto ascii.
+        let c = match c {
+            '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+            _ => c,
+        };
+
+        let mapped = match c {
+            ' ' | '-' | '_' | '.' | '\'' => continue,
+            'I' | 'i' | 'l' | '1' | 'ӏ' | 'Ι' | 'ι' | 'і' | 'І' | '|' => 'l',
+            '0' | 'о' | 'О' | 'ο' | 'Ο' => 'o',
+            'а' | 'А' | 'α' | 'Α' => 'a',
+            'в' | 'В' | 'β' | 'Β' => 'b',
+            'с' | 'С' | 'ϲ' => 'c',
+            'ԁ' => 'd',
+            'е' | 'Е' | 'ε' | 'Ε' | '3' => 'e',
+            'һ' | 'Н' | 'н' | 'Η' => 'h',
+            'ј' | 'Ј' => 'j',
+            'к' | 'К' | 'κ' | 'Κ' => 'k',
+            'м' | 'М' | 'Μ' => 'm',
+            'ν' | 'Ν' => 'v',
+            'р' | 'Р' | 'ρ' | 'Ρ' => 'p',
+            'ѕ' | 'Ѕ' | '5' | '$' => 's',
+            'т' | 'Т' | 'τ' | 'Τ' | '7' => 't',
+            'у' | 'У' | 'υ' | 'Υ' => 'y',
+            'х' | 'Х' | 'χ' | 'Χ' => 'x',
+            'Ζ' => 'z',
+            _ => {
+                skeleton.extend(c.to_lowercase());
+                continue;
+            }
+        };
+        skeleton.push(mapped);
+    }
+
+    skeleton.replace("rn", "m").replace("vv", "w")
+}
+
+/**
+ * Check a name against the naming rules, returns the normalized name.
+ */
+pub fn validate(name: &Value) -> Result<String, String> {
+    let name = match name.as_str() {
+        Some(name) => normalize(name),
+        None => return Err(String::from("Name must be a string")),
+    };
+
+    let length = name.chars().count();
+    if length == 0 || length > MAX_LENGTH {
+        return Err(format!("Name must be between 1 and {} characters", MAX_LENGTH));
+    }
+
+    if let Some(c) = name.chars().find(|c| !c.is_alphanumeric() && !" -_.'".contains(*c)) {
+        return Err(format!("Name contains an invalid character ({:?})", c));
+    }
+
+    let skeleton = skeleton(&name);
+    if skeleton.is_empty() {
+        return Err(String::from("Name must contain a letter or number"));
+    }
+
+    if RESERVED.iter().any(|reserved| skeleton == self::skeleton(reserved)) {
+        return Err(String::from("Name is reserved"));
+    }
+
+    Ok(name)
+}
+
+/**
+ * Check if a name looks like the name of an online user.
+ */
+pub fn is_taken(name: &str) -> bool {
+    let skeleton = skeleton(name);
+    unsafe { USERS.iter().any(|user| self::skeleton(&user.name) == skeleton) }
+}
+
+/**
+ * Make sure a valid name isn't used by an online user, following the name policy.
+ */
+pub fn make_unique(name: String) -> Result<String, String> {
+    if !is_taken(&name) {
+        return Ok(name);
+    }
+
+    if unsafe { NAME_POLICY } == NamePolicy::Reject {
+        return Err(String::from("Name is already in use"));
+    }
+
+    // Add a number to the end of the name until it's free:
+    for n in 2.. {
+        let suffix = format!(" {}", n);
+        let base: String = name.chars().take(MAX_LENGTH - suffix.len()).collect();
+        let candidate = format!("{}{}", base.trim_end(), suffix);
+
+        if !is_taken(&candidate) {
+            return Ok(candidate);
+        }
+    }
+
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{normalize, skeleton, validate, MAX_LENGTH};
+
+    #[test]
+    fn skeleton_folds_lookalikes() {
+        assert_eq!(skeleton("admin"), skeleton("ΑDMIN"));
+        assert_eq!(skeleton("admin"), skeleton("Adm1n"));
+        assert_eq!(skeleton("admin"), skeleton("ａｄｍｉｎ"));
+        assert_eq!(skeleton("bob"), skeleton("B0B"));
+        assert_eq!(skeleton("modern"), skeleton("modem"));
+        assert_eq!(skeleton("wave"), skeleton("vvave"));
+        assert_eq!(skeleton("mary ann"), skeleton("Mary_Ann"));
+        assert_ne!(skeleton("alice"), skeleton("bob"));
+    }
+
+    #[test]
+    fn normalize_whitespace() {
+        assert_eq!(normalize("  Mary \t Ann \n"), "Mary Ann");
+    }
+
+    #[test]
+    fn validate_names() {
+        assert_eq!(validate(&json!(" Mary  Ann ")), Ok(String::from("Mary Ann")));
+        assert_eq!(validate(&json!("Zoë-o'Neil")), Ok(String::from("Zoë-o'Neil")));
+        assert!(validate(&json!(42)).is_err());
+        assert!(validate(&json!("")).is_err());
+        assert!(validate(&json!("   ")).is_err());
+        assert!(validate(&json!("a".repeat(MAX_LENGTH + 1))).is_err());
+        assert!(validate(&json!("a".repeat(MAX_LENGTH))).is_ok());
+        assert!(validate(&json!("<script>")).is_err());
+        assert!(validate(&json!("_-_")).is_err());
+    }
+
+    #[test]
+    fn validate_reserved_names() {
+        for name in ["admin", "Admin", "ΑDMIN", "s y s t e m", "r00t", "Moderator", "FLOW"] {
+            assert_eq!(validate(&json!(name)), Err(String::from("Name is reserved")), "{}", name);
+        }
+        assert!(validate(&json!("admins")).is_ok());
+    }
+}
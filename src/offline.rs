@@ -0,0 +1,195 @@
+use std::{sync::atomic::{AtomicBool, Ordering}, time::Duration};
+
+use serde_json::{json, Value};
+
+use crate::{messages, store, OFFERS};
+
+/**
+ * How long a message waits for its recipient before it's dropped.
+ */
+pub const OFFLINE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/**
+ * Most messages waiting for a single recipient.
+ */
+pub const MAX_PER_RECIPIENT: usize = 100;
+
+/**
+ * A message waiting for a registered user to come online.
+ */
+#[derive(Debug)]
+pub struct Queued {
+    pub target: String,
+    pub content: String,
+    pub offer: Option<String>,
+    pub expires: u64,
+}
+
+pub static mut QUEUE: Vec<Queued> = Vec::new();
+static DIRTY: AtomicBool = AtomicBool::new(false);
+
+/**
+ * Load the queue from the store.
+ */
+pub fn load() {
+    let Some(Value::Array(queue)) = store::load("offline") else { return };
+
+    let queue = queue.iter().filter_map(|queued| Some(Queued {
+        target: queued["target"].as_str()?.to_string(),
+        content: queued["content"].as_str()?.to_string(),
+        offer: queued["offer"].as_str().map(String::from),
+        expires: queued["expires"].as_u64()?,
+    }));
+
+    unsafe { QUEUE = queue.collect(); }
+}
+
+/**
+ * Write the queue to the store if it changed.
+ */
+pub fn flush() {
+    if !DIRTY.swap(false, Ordering::Relaxed) {
+        return;
+    }
+
+    let queue: Vec<Value> = unsafe {
+        QUEUE.iter().map(|queued| json!({
+            "target": queued.target,
+            "content": queued.content,
+            "offer": queued.offer,
+            "expires": queued.expires
+        })).collect()
+    };
+
+    store::save("offline", &Value::Array(queue));
+}
+
+/**
+ * Drop the messages which waited too long.
+ */
+fn prune() {
+    let now = messages::timestamp();
+    unsafe {
+        let len = QUEUE.len();
+        QUEUE.retain(|queued| queued.expires > now);
+        if QUEUE.len() != len {
+            DIRTY.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/**
+ * Queue a message for an offline user, offers are dropped if they're gone by the time it's delivered.
+ */
+pub fn push(target: &str, content: String, offer: Option<String>) -> Result<(), String> {
+    prune();
+
+    unsafe {
+        if QUEUE.iter().filter(|queued| queued.target == target).count() >= MAX_PER_RECIPIENT {
+            return Err(String::from("Recipient has too many waiting messages"));
+        }
+
+        QUEUE.push(Queued {
+            target: target.to_string(),
+            content,
+            offer,
+            expires: messages::timestamp() + OFFLINE_TTL.as_millis() as u64,
+        });
+    }
+
+    DIRTY.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/**
+ * Take all messages waiting for a user, oldest first.
+ */
+pub fn take(target: &str) -> Vec<String> {
+    prune();
+
+    let mut waiting = Vec::new();
+    unsafe {
+        let len = QUEUE.len();
+        QUEUE.retain(|queued| {
+            if queued.target != target {
+                return true;
+            }
+
+            let offer_exists = queued.offer.as_ref().is_none_or(|id| OFFERS.iter().any(|offer| offer.id == *id));
+            if offer_exists {
+                waiting.push(queued.content.clone());
+            }
+            false
+        });
+
+        if QUEUE.len() != len {
+            DIRTY.store(true, Ordering::Relaxed);
+        }
+    }
+
+    waiting
+}
+
+#[cfg(test)]
+mod tests {
+    use serial_test::serial;
+
+    use super::{push, take, Queued, MAX_PER_RECIPIENT, QUEUE};
+    use crate::{messages, Offer, OFFERS};
+
+    #[test]
+    #[serial]
+    fn messages_wait_for_their_recipient() {
+        unsafe { QUEUE.clear(); }
+
+        push("alice", String::from("one"), None).unwrap();
+        push("bob", String::from("other"), None).unwrap();
+        push("alice", String::from("two"), None).unwrap();
+
+        assert_eq!(take("alice"), ["one", "two"]);
+        assert!(take("alice").is_empty());
+        assert_eq!(take("bob"), ["other"]);
+    }
+
+    #[test]
+    #[serial]
+    fn expired_messages_are_dropped() {
+        unsafe {
+            QUEUE.clear();
+            QUEUE.push(Queued { target: String::from("alice"), content: String::from("old"), offer: None, expires: messages::timestamp() - 1 });
+        }
+        push("alice", String::from("new"), None).unwrap();
+
+        assert_eq!(take("alice"), ["new"]);
+    }
+
+    #[test]
+    #[serial]
+    fn recipients_are_capped() {
+        unsafe { QUEUE.clear(); }
+
+        for _ in 0..MAX_PER_RECIPIENT {
+            push("alice", String::from("spam"), None).unwrap();
+        }
+        assert!(push("alice", String::from("more"), None).is_err());
+        assert!(push("bob", String::from("fine"), None).is_ok());
+
+        assert_eq!(take("alice").len(), MAX_PER_RECIPIENT);
+        assert!(push("alice", String::from("again"), None).is_ok());
+    }
+
+    #[test]
+    #[serial]
+    fn gone_offers_are_dropped() {
+        unsafe {
+            QUEUE.clear();
+            OFFERS.clear();
+            OFFERS.push(Offer { origin: String::from("bob"), target: String::from("alice"), id: String::from("open") });
+        }
+        push("alice", String::from("open offer"), Some(String::from("open"))).unwrap();
+        push("alice", String::from("gone offer"), Some(String::from("gone"))).unwrap();
+
+        assert_eq!(take("alice"), ["open offer"]);
+        unsafe { OFFERS.clear(); }
+    }
+}
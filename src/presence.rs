@@ -0,0 +1,116 @@
+use std::{net::SocketAddr, time::{Duration, Instant}};
+
+use serde_json::{json, Value};
+
+use crate::{protocol, send::send_only, USERS};
+
+/**
+ * Longest custom status text a user can set.
+ */
+pub const MAX_STATUS_LENGTH: usize = 128;
+
+/**
+ * How long a typing indicator lasts unless the user refreshes it.
+ */
+pub const TYPING_TIMEOUT: Duration = Duration::from_secs(6);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Online,
+    Away,
+    Busy,
+    Invisible,
+}
+
+impl Status {
+    pub fn parse(status: &str) -> Option<Status> {
+        match status {
+            "online" => Some(Status::Online),
+            "away" => Some(Status::Away),
+            "busy" => Some(Status::Busy),
+            "invisible" => Some(Status::Invisible),
+            _ => None,
+        }
+    }
+
+    /**
+     * The status as other users see it, invisible users appear offline.
+     */
+    pub fn public_str(&self) -> &'static str {
+        match self {
+            Status::Online => "online",
+            Status::Away => "away",
+            Status::Busy => "busy",
+            Status::Invisible => "offline",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Presence {
+    pub status: Status,
+    pub text: Option<String>,
+}
+
+impl Presence {
+    pub fn new() -> Presence {
+        Presence { status: Status::Online, text: None }
+    }
+
+    /**
+     * The presence as other users see it, invisible users don't show their status text.
+     */
+    pub fn public_json(&self) -> Value {
+        let text = match self.status {
+            Status::Invisible => None,
+            _ => self.text.clone(),
+        };
+
+        json!({
+            "status": self.status.public_str(),
+            "text": text
+        })
+    }
+}
+
+/**
+ * Send a typing event to the relevant users.
+ * With a target only they get it, otherwise everyone else who supports typing events.
+ */
+pub async fn send_typing(sender: SocketAddr, target: Option<&str>, typing_json: &Value) {
+    let recipients: Vec<SocketAddr> = unsafe {
+        USERS.iter()
+            .filter(|user| user.addr != sender && user.reconnecting.is_none())
+            .filter(|user| target.is_none() || target == Some(user.id.as_str()))
+            .filter(|user| protocol::has_feature(user.addr, "typing"))
+            .map(|user| user.addr)
+            .collect()
+    };
+
+    for addr in recipients {
+        send_only(addr, typing_json.to_string()).await;
+    }
+}
+
+/**
+ * Stop the typing indicator of a user if it wasn't refreshed since the given moment.
+ */
+pub async fn expire_typing(id: String, since: Instant) {
+    tokio::time::sleep(TYPING_TIMEOUT).await;
+
+    let user = unsafe { USERS.iter_mut().find(|user| user.id == id && user.typing.as_ref().map(|(at, _)| *at) == Some(since)) };
+    let Some(user) = user else { return };
+
+    let (_, target) = user.typing.take().unwrap();
+    let addr = user.addr;
+    let typing_json = json!({
+        "type": "typing",
+        "user": {
+            "id": user.id,
+            "name": user.name
+        },
+        "typing": false
+    });
+
+    send_typing(addr, target.as_deref(), &typing_json).await;
+}
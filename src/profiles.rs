@@ -0,0 +1,245 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use flow_protocol::{Avatar, Profile};
+use serde_json::{json, Value};
+
+use crate::{presence, store};
+
+pub const MAX_DISPLAY_NAME_LENGTH: usize = 64;
+pub const MAX_BIO_LENGTH: usize = 1000;
+pub const MAX_TIMEZONE_LENGTH: usize = 64;
+pub const MAX_AVATAR_NAME_LENGTH: usize = 128;
+
+/**
+ * Most bytes of base64 an avatar can be.
+ */
+pub const MAX_AVATAR_SIZE: usize = 256 << 10;
+
+/**
+ * The file extensions an avatar can have.
+ */
+const AVATAR_TYPES: [&str; 5] = ["png", "jpg", "jpeg", "gif", "webp"];
+
+/**
+ * The profile of a registered user, by their account id.
+ */
+#[derive(Debug)]
+pub struct AccountProfile {
+    pub account: String,
+    pub profile: Profile,
+}
+
+pub static mut PROFILES: Vec<AccountProfile> = Vec::new();
+static DIRTY: AtomicBool = AtomicBool::new(false);
+
+/**
+ * Load the profiles from the store.
+ */
+pub fn load() {
+    let Some(Value::Array(profiles)) = store::load("profiles") else { return };
+
+    let profiles = profiles.into_iter().filter_map(|profile| Some(AccountProfile {
+        account: profile["account"].as_str()?.to_string(),
+        profile: serde_json::from_value(profile).ok()?,
+    }));
+
+    unsafe { PROFILES = profiles.collect(); }
+}
+
+/**
+ * Write the profiles to the store if they changed.
+ */
+pub fn flush() {
+    if !DIRTY.swap(false, Ordering::Relaxed) {
+        return;
+    }
+
+    let profiles: Vec<Value> = unsafe {
+        PROFILES.iter().map(|profile| {
+            let mut profile_json = json!(profile.profile);
+            profile_json["account"] = json!(profile.account);
+            profile_json
+        }).collect()
+    };
+
+    store::save("profiles", &Value::Array(profiles));
+}
+
+/**
+ * Get the profile of an account, it's created when it doesn't exist yet.
+ */
+fn profile<'a>(account: &str) -> &'a mut Profile {
+    unsafe {
+        if let Some(index) = PROFILES.iter().position(|profile| profile.account == account) {
+            return &mut PROFILES[index].profile;
+        }
+
+        PROFILES.push(AccountProfile { account: account.to_string(), profile: Profile::default() });
+        &mut PROFILES.last_mut().unwrap().profile
+    }
+}
+
+/**
+ * Check the new value of a text field, an empty string or null clears it.
+ */
+fn text(value: &Value, field: &str, max: usize, multiline: bool) -> Result<Option<String>, String> {
+    let text = match value {
+        Value::Null => return Ok(None),
+        Value::String(text) => text.trim(),
+        _ => return Err(format!("{} must be a string", field)),
+    };
+
+    if text.chars().count() > max {
+        return Err(format!("{} can be at most {} characters", field, max));
+    }
+    if text.chars().any(|c| c.is_control() && !(multiline && c == '\n')) {
+        return Err(format!("{} contains an invalid character", field));
+    }
+
+    Ok(Some(text.to_string()).filter(|text| !text.is_empty()))
+}
+
+/**
+ * Change the fields of a profile that are in the update, the rest stays the same.
+ * { display_name: "name", bio: "about me", status: "status", timezone: "Europe/Amsterdam" }
+ */
+pub fn update(account: &str, update: &Value) -> Result<(), String> {
+    let Value::Object(fields) = update else { return Err(String::from("Update must be an object")) };
+
+    // Check every field before changing any of them:
+    let mut changes = Vec::new();
+    for (field, value) in fields.iter() {
+        let (max, multiline) = match field.as_str() {
+            "display_name" => (MAX_DISPLAY_NAME_LENGTH, false),
+            "bio" => (MAX_BIO_LENGTH, true),
+            "status" => (presence::MAX_STATUS_LENGTH, false),
+            "timezone" => (MAX_TIMEZONE_LENGTH, false),
+            _ => return Err(format!("Unknown profile field \"{}\"", field)),
+        };
+        let value = text(value, field, max, multiline)?;
+
+        if field == "timezone" && value.as_ref().is_some_and(|tz| !tz.chars().all(|c| c.is_ascii_alphanumeric() || "/_+-:".contains(c))) {
+            return Err(String::from("timezone must be a name like \"Europe/Amsterdam\" or an offset like \"+02:00\""));
+        }
+        changes.push((field.as_str(), value));
+    }
+
+    let profile = profile(account);
+    for (field, value) in changes {
+        match field {
+            "display_name" => profile.display_name = value,
+            "bio" => profile.bio = value,
+            "status" => profile.status = value,
+            "timezone" => profile.timezone = value,
+            _ => {}
+        }
+    }
+
+    DIRTY.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/**
+ * Give an account a new avatar, it has to be a small image.
+ */
+pub fn set_avatar(account: &str, name: &str, content: &str) -> Result<(), String> {
+    if name.chars().count() > MAX_AVATAR_NAME_LENGTH {
+        return Err(format!("Avatar name can be at most {} characters", MAX_AVATAR_NAME_LENGTH));
+    }
+    let extension = name.rsplit_once('.').map(|(_, extension)| extension.to_lowercase()).unwrap_or_default();
+    if !AVATAR_TYPES.contains(&extension.as_str()) {
+        return Err(format!("Avatar must be an image ({})", AVATAR_TYPES.join(", ")));
+    }
+    if content.len() > MAX_AVATAR_SIZE {
+        return Err(format!("Avatar can be at most {} bytes", MAX_AVATAR_SIZE));
+    }
+    if content.is_empty() || !content.bytes().all(|c| c.is_ascii_alphanumeric() || b"+/=".contains(&c)) {
+        return Err(String::from("Avatar content must be base64"));
+    }
+
+    profile(account).avatar = Some(Avatar { name: name.to_string(), content: content.to_string() });
+    DIRTY.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/**
+ * The profile of an account as it's send to clients, unset fields are null.
+ * Without the avatar content only its name is in there, clients ask for the profile to get the image.
+ * { display_name, avatar: { name, content } | null, bio, status, timezone }
+ */
+pub fn json(account: &str, avatar_content: bool) -> Value {
+    let profile = unsafe { PROFILES.iter().find(|profile| profile.account == account) };
+    let mut profile_json = match profile {
+        Some(profile) => json!(profile.profile),
+        None => json!(Profile::default()),
+    };
+
+    if !avatar_content {
+        if let Some(avatar) = profile_json["avatar"].as_object_mut() {
+            avatar.remove("content");
+        }
+    }
+    profile_json
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use serial_test::serial;
+
+    use super::{set_avatar, text, update, MAX_AVATAR_SIZE, MAX_BIO_LENGTH, MAX_DISPLAY_NAME_LENGTH};
+
+    #[test]
+    fn text_fields() {
+        assert_eq!(text(&Value::Null, "bio", 10, false), Ok(None));
+        assert_eq!(text(&json!("  "), "bio", 10, false), Ok(None));
+        assert_eq!(text(&json!(" hi "), "bio", 10, false), Ok(Some(String::from("hi"))));
+        assert_eq!(text(&json!("ééééé"), "bio", 5, false), Ok(Some(String::from("ééééé"))));
+        assert_eq!(text(&json!("toolong"), "bio", 5, false), Err(String::from("bio can be at most 5 characters")));
+        assert_eq!(text(&json!(5), "bio", 5, false), Err(String::from("bio must be a string")));
+        assert!(text(&json!("a\nb"), "bio", 10, false).is_err());
+        assert!(text(&json!("a\nb"), "bio", 10, true).is_ok());
+        assert!(text(&json!("a\u{7}b"), "bio", 10, true).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn update_profile() {
+        update("update", &json!({ "display_name": "Alice", "bio": "line\nline", "timezone": "Europe/Amsterdam" })).unwrap();
+        update("update", &json!({ "timezone": "+02:00", "bio": null })).unwrap();
+
+        let profile = super::json("update", true);
+        assert_eq!(profile["display_name"], "Alice");
+        assert_eq!(profile["bio"], Value::Null);
+        assert_eq!(profile["timezone"], "+02:00");
+    }
+
+    #[test]
+    #[serial]
+    fn update_checks_every_field_first() {
+        update("checks", &json!({ "display_name": "Bob" })).unwrap();
+
+        assert!(update("checks", &json!({ "display_name": "Eve", "unknown": "x" })).is_err());
+        assert!(update("checks", &json!({ "display_name": "Eve", "bio": "b".repeat(MAX_BIO_LENGTH + 1) })).is_err());
+        assert!(update("checks", &json!({ "display_name": "d".repeat(MAX_DISPLAY_NAME_LENGTH + 1) })).is_err());
+        assert!(update("checks", &json!({ "timezone": "Europe/Amsterdam; rm" })).is_err());
+        assert!(update("checks", &json!(["display_name"])).is_err());
+
+        assert_eq!(super::json("checks", true)["display_name"], "Bob");
+    }
+
+    #[test]
+    #[serial]
+    fn avatars() {
+        assert!(set_avatar("avatar", "me.exe", "aGVsbG8=").is_err());
+        assert!(set_avatar("avatar", "me", "aGVsbG8=").is_err());
+        assert!(set_avatar("avatar", "me.png", "").is_err());
+        assert!(set_avatar("avatar", "me.png", "not base64!").is_err());
+        assert!(set_avatar("avatar", "me.png", &"a".repeat(MAX_AVATAR_SIZE + 1)).is_err());
+        assert_eq!(super::json("avatar", true)["avatar"], Value::Null);
+
+        set_avatar("avatar", "Me.PNG", "aGVsbG8=").unwrap();
+        assert_eq!(super::json("avatar", true)["avatar"], json!({ "name": "Me.PNG", "content": "aGVsbG8=" }));
+        assert_eq!(super::json("avatar", false)["avatar"], json!({ "name": "Me.PNG" }));
+    }
+}
@@ -0,0 +1,125 @@
+use std::net::SocketAddr;
+
+use serde_json::{json, Value};
+
+use crate::{names, presence, profiles, resume, FluxUser};
+
+pub use flow_protocol::{FEATURES, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+
+/**
+ * Largest message (in bytes) the server accepts from a client.
+ */
+pub const MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+/**
+ * What a connection agreed on using the hello message.
+ * Connections which never send a hello get the current version with all features.
+ */
+#[derive(Debug)]
+pub struct Negotiated {
+    pub addr: SocketAddr,
+    pub protocol: u64,
+    pub features: Vec<String>,
+}
+
+pub static mut NEGOTIATED: Vec<Negotiated> = Vec::new();
+
+/**
+ * Pick the highest protocol version both sides support.
+ */
+pub fn pick_version(versions: &[u64]) -> Option<u64> {
+    versions.iter()
+        .copied()
+        .filter(|version| (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(version))
+        .max()
+}
+
+/**
+ * Get the protocol version and features of a connection.
+ */
+pub fn negotiated(addr: SocketAddr) -> (u64, Vec<String>) {
+    unsafe {
+        match NEGOTIATED.iter().find(|negotiated| negotiated.addr == addr) {
+            Some(negotiated) => (negotiated.protocol, negotiated.features.clone()),
+            None => (PROTOCOL_VERSION, FEATURES.iter().map(|feature| feature.to_string()).collect()),
+        }
+    }
+}
+
+/**
+ * Check if a connection agreed on using a feature.
+ */
+pub fn has_feature(addr: SocketAddr, feature: &str) -> bool {
+    negotiated(addr).1.iter().any(|other| other == feature)
+}
+
+/**
+ * Get the feature a client message needs, messages of types flow doesn't know about need none.
+ */
+pub fn required_feature(msg_type: &str, json: &Value) -> Option<&'static str> {
+    match msg_type {
+        "chat" if json["target"] != Value::Null => Some("direct"),
+        "chat" if json["parent"] != Value::Null => Some("threads"),
+        "chat" => Some("chat"),
+        "edit" | "delete" | "history" => Some("history"),
+        "react" => Some("reactions"),
+        "read" => Some("receipts"),
+        "file" => Some("file"),
+        "request" | "offer" | "session" => Some("p2p"),
+        "resume" => Some("resume"),
+        "presence" => Some("presence"),
+        "typing" => Some("typing"),
+        "block" => Some("blocks"),
+        "rename" => Some("rename"),
+        "profile" => Some("profiles"),
+        _ => None,
+    }
+}
+
+/**
+ * Get the feature a client needs to get a chat message, reactions and edits follow their message.
+ */
+pub fn entry_feature(entry: &Value) -> &'static str {
+    match entry["type"].as_str() {
+        Some("file") => "file",
+        _ => required_feature("chat", entry).unwrap_or("chat"),
+    }
+}
+
+/**
+ * Forget what a connection negotiated once it's closed.
+ */
+pub fn forget(addr: SocketAddr) {
+    unsafe { NEGOTIATED.retain(|negotiated| negotiated.addr != addr); }
+}
+
+/**
+ * Create the welcome message which tells a user who they are and how the server is configured.
+ * { type: "welcome", user: { id, name }, server, version, protocol, features, limits }
+ */
+pub fn welcome_json(user: &FluxUser) -> Value {
+    let (protocol, features) = negotiated(user.addr);
+
+    json!({
+        "type": "welcome",
+        "user": {
+            "id": user.id,
+            "name": user.name,
+            "role": user.role.as_str(),
+            "bot": user.bot
+        },
+        "server": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "protocol": protocol,
+        "features": features,
+        "limits": {
+            "max_message_size": MAX_MESSAGE_SIZE,
+            "max_name_length": names::MAX_LENGTH,
+            "resume_grace": unsafe { resume::RESUME_GRACE.as_secs() },
+            "max_queue": resume::MAX_QUEUE,
+            "max_status_length": presence::MAX_STATUS_LENGTH,
+            "max_avatar_size": profiles::MAX_AVATAR_SIZE,
+            "typing_timeout": presence::TYPING_TIMEOUT.as_secs()
+        }
+    })
+}
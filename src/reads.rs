@@ -0,0 +1,162 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde_json::{json, Map, Value};
+
+use crate::{blocks, history::{self, HISTORY}, store};
+
+/**
+ * The last message a user has read in a conversation.
+ * Conversations are "public" or the id of the other user in a direct message.
+ */
+#[derive(Debug)]
+pub struct Read {
+    pub user: String,
+    pub conversation: String,
+    pub id: u64,
+}
+
+pub static mut READS: Vec<Read> = Vec::new();
+static DIRTY: AtomicBool = AtomicBool::new(false);
+
+/**
+ * Load the read markers from the store.
+ */
+pub fn load() {
+    let Some(Value::Object(users)) = store::load("reads") else { return };
+
+    let mut reads = Vec::new();
+    for (user, conversations) in users.iter() {
+        for (conversation, id) in conversations.as_object().into_iter().flatten() {
+            if let Some(id) = id.as_u64() {
+                reads.push(Read { user: user.clone(), conversation: conversation.clone(), id });
+            }
+        }
+    }
+
+    unsafe { READS = reads; }
+}
+
+/**
+ * Write the read markers to the store if they changed.
+ */
+pub fn flush() {
+    if !DIRTY.swap(false, Ordering::Relaxed) {
+        return;
+    }
+
+    let mut users = Map::new();
+    unsafe {
+        for read in READS.iter() {
+            let conversations = users.entry(read.user.clone()).or_insert(json!({}));
+            conversations[&read.conversation] = json!(read.id);
+        }
+    }
+
+    store::save("reads", &Value::Object(users));
+}
+
+/**
+ * Get the last message a user has read in a conversation.
+ */
+pub fn get(user: &str, conversation: &str) -> u64 {
+    unsafe {
+        READS.iter()
+            .find(|read| read.user == user && read.conversation == conversation)
+            .map_or(0, |read| read.id)
+    }
+}
+
+/**
+ * Move the read marker of a user forward, returns false if it already was past the id.
+ */
+pub fn mark(user: &str, conversation: &str, id: u64) -> bool {
+    unsafe {
+        match READS.iter_mut().find(|read| read.user == user && read.conversation == conversation) {
+            Some(read) if read.id >= id => return false,
+            Some(read) => read.id = id,
+            None => READS.push(Read { user: user.to_string(), conversation: conversation.to_string(), id }),
+        }
+    }
+
+    DIRTY.store(true, Ordering::Relaxed);
+    true
+}
+
+/**
+ * Count the unread messages of a user per conversation, hidden messages don't count.
+ * { "public": 3, "user_id": 1 }
+ */
+pub fn unread_json(user: &str) -> Value {
+    let mut unread = Map::new();
+
+    unsafe {
+        for entry in HISTORY.iter() {
+            let sender = entry["sender"]["id"].as_str().unwrap_or_default();
+            if !history::visible(entry, user) || sender == user || entry["deleted"] != Value::Null || blocks::hides(user, entry) {
+                continue;
+            }
+
+            let conversation = history::conversation(entry, user);
+            if entry["id"].as_u64().unwrap_or(0) > get(user, &conversation) {
+                let count = unread.entry(conversation).or_insert(json!(0));
+                *count = json!(count.as_u64().unwrap_or(0) + 1);
+            }
+        }
+    }
+
+    Value::Object(unread)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use serial_test::serial;
+
+    use super::{get, mark, unread_json, READS};
+    use crate::{blocks::{self, BLOCKS}, history::{self, HISTORY}};
+
+    #[test]
+    #[serial]
+    fn marks_only_move_forward() {
+        unsafe { READS.clear(); }
+
+        assert_eq!(get("alice", "public"), 0);
+        assert!(mark("alice", "public", 5));
+        assert!(!mark("alice", "public", 5));
+        assert!(!mark("alice", "public", 3));
+        assert!(mark("alice", "public", 8));
+        assert_eq!(get("alice", "public"), 8);
+        assert_eq!(get("alice", "bob"), 0);
+    }
+
+    #[test]
+    #[serial]
+    fn unread_counts() {
+        unsafe {
+            READS.clear();
+            HISTORY.clear();
+            BLOCKS.clear();
+        }
+        let chat = |id: u64, sender: &str, target: Option<&str>| {
+            history::push(&json!({ "type": "chat", "id": id, "sender": { "id": sender }, "target": target, "content": "hi" }));
+        };
+        chat(1, "bob", None);
+        chat(2, "bob", None);
+        chat(3, "alice", None);
+        chat(4, "bob", Some("alice"));
+        chat(5, "bob", Some("carol"));
+        chat(6, "carol", None);
+        chat(7, "bob", None);
+        history::delete(7, "bob", 10);
+
+        assert_eq!(unread_json("alice"), json!({ "public": 3, "bob": 1 }));
+
+        mark("alice", "public", 2);
+        assert_eq!(unread_json("alice"), json!({ "public": 1, "bob": 1 }));
+
+        blocks::block("alice", "bob", false).unwrap();
+        assert_eq!(unread_json("alice"), json!({ "public": 1 }));
+        blocks::block("alice", "carol", true).unwrap();
+        assert_eq!(unread_json("alice"), json!({}));
+    }
+}
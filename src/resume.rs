@@ -0,0 +1,79 @@
+use std::{net::SocketAddr, time::{Duration, Instant}};
+
+use colored::*;
+use serde_json::json;
+
+use crate::{info, remove_user, send::send_feature, USERS};
+
+/**
+ * How long a dropped user can resume their session before they're removed.
+ */
+pub static mut RESUME_GRACE: Duration = Duration::from_secs(30);
+
+/**
+ * How many messages are kept for a reconnecting user, older ones are dropped first.
+ */
+pub const MAX_QUEUE: usize = 256;
+
+/**
+ * Called when the socket of a user drops.
+ * Instead of removing them right away they're marked as reconnecting for the grace period.
+ */
+pub async fn suspend(addr: SocketAddr) {
+    let since = Instant::now();
+    let user = unsafe { USERS.iter_mut().find(|user| user.addr == addr && user.reconnecting.is_none()) };
+
+    let user = match user {
+        Some(user) => user,
+        None => {
+            info::info("Hard Disconnect".red(), addr.to_string());
+            return;
+        }
+    };
+
+    info::info("Reconnecting".yellow(), user.name.clone());
+    user.reconnecting = Some(since);
+    let id = user.id.clone();
+
+    // Let the other users know this user might come back:
+    let update_json = json!({
+        "type": "reconnecting",
+        "user": {
+            "id": user.id,
+            "name": user.name
+        }
+    });
+    send_feature(addr, "resume", update_json.to_string()).await;
+
+    // Remove the user once the grace period is over, unless they resumed in the meantime.
+    tokio::spawn(async move {
+        tokio::time::sleep(unsafe { RESUME_GRACE }).await;
+        expire(&id, since).await;
+    });
+}
+
+/**
+ * Remove a reconnecting user if they've been gone since the given moment.
+ */
+pub async fn expire(id: &str, since: Instant) {
+    let addr = unsafe {
+        USERS.iter()
+            .find(|user| user.id == id && user.reconnecting == Some(since))
+            .map(|user| user.addr)
+    };
+
+    if let Some(addr) = addr {
+        remove_user(addr).await;
+    }
+}
+
+/**
+ * Remove a reconnecting user who still holds an address, so a new connection can use it.
+ */
+pub async fn release(addr: SocketAddr) {
+    let stale = unsafe { USERS.iter().any(|user| user.addr == addr && user.reconnecting.is_some()) };
+
+    if stale {
+        remove_user(addr).await;
+    }
+}
@@ -0,0 +1,95 @@
+use std::{fmt, net::SocketAddr};
+
+use crate::USERS;
+
+/**
+ * What a user is allowed to do, guests are users without an account.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Admin,
+    Moderator,
+    Member,
+    Guest,
+}
+
+/**
+ * Actions that not every role is allowed to do.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    Chat,
+    React,
+    File,
+    P2p,
+    Moderate,
+    Administer,
+}
+
+impl Role {
+    pub fn parse(role: &str) -> Option<Role> {
+        match role {
+            "admin" => Some(Role::Admin),
+            "moderator" => Some(Role::Moderator),
+            "member" => Some(Role::Member),
+            "guest" => Some(Role::Guest),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Moderator => "moderator",
+            Role::Member => "member",
+            Role::Guest => "guest",
+        }
+    }
+
+    /**
+     * The permission matrix.
+     */
+    pub fn can(&self, permission: Permission) -> bool {
+        match permission {
+            Permission::Chat | Permission::React => true,
+            Permission::File | Permission::P2p => *self != Role::Guest,
+            Permission::Moderate => matches!(self, Role::Admin | Role::Moderator),
+            Permission::Administer => *self == Role::Admin,
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Permission {
+    /**
+     * Get the permission a message type needs, if any.
+     */
+    pub fn of(msg_type: &str) -> Option<Permission> {
+        match msg_type {
+            "chat" | "edit" => Some(Permission::Chat),
+            "react" => Some(Permission::React),
+            "file" => Some(Permission::File),
+            "request" => Some(Permission::P2p),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * Check if the role of a user allows a message type, before it's dispatched.
+ * Users that aren't logged in are left to the handlers.
+ */
+pub fn check(addr: SocketAddr, msg_type: &str) -> Option<String> {
+    let permission = Permission::of(msg_type)?;
+    let role = unsafe { USERS.iter().find(|user| user.addr == addr)?.role };
+
+    match role.can(permission) {
+        true => None,
+        false => Some(format!("Access declined ({} can't send {})", role, msg_type)),
+    }
+}
@@ -0,0 +1,336 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex, OnceLock, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use colored::*;
+use rhai::{CallFnOptions, Dynamic, Engine, Map, NativeCallContext, Scope, AST};
+use serde_json::{json, Value};
+
+use crate::{
+    events::{self, Event},
+    handlers::{Context, HandlerFuture, MessageHandler},
+    info::{self, get_user_id},
+    send::{disconnect, send_all, send_only, SERVER_ADDR},
+    FluxUser, USERS,
+};
+
+/**
+ * How often the scripts directory is checked for changes.
+ */
+pub const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+
+/**
+ * Most operations a single script call can do before it's stopped.
+ */
+pub const MAX_OPERATIONS: u64 = 100_000;
+
+/**
+ * A handler a script registered with `on_event` or `on_message`.
+ */
+#[derive(Debug, Clone)]
+enum Hook {
+    Event(String, String),
+    Message(String, String),
+}
+
+struct Script {
+    path: PathBuf,
+    ast: AST,
+    hooks: Vec<Hook>,
+}
+
+/**
+ * Something a script wants to send, it goes out after the script is done.
+ */
+enum Outgoing {
+    Only(String, String),
+    All(String),
+    Kick(String, String),
+}
+
+/**
+ * What a single call wants to send, it's passed to the script functions as the call's tag.
+ */
+type Outbox = Arc<Mutex<Vec<Outgoing>>>;
+
+static ENGINE: OnceLock<Engine> = OnceLock::new();
+static SCRIPTS: RwLock<Vec<Script>> = RwLock::new(Vec::new());
+static REGISTERING: Mutex<Vec<Hook>> = Mutex::new(Vec::new());
+
+/**
+ * The scripts found by the last load, including the ones that failed, to know when to reload.
+ */
+static SCANNED: Mutex<Vec<(PathBuf, SystemTime)>> = Mutex::new(Vec::new());
+
+/**
+ * The engine scripts run in, it can only reach the server through the functions registered here.
+ */
+fn engine() -> &'static Engine {
+    ENGINE.get_or_init(|| {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_call_levels(32);
+        engine.set_max_string_size(64 << 10);
+        engine.set_max_array_size(10_000);
+        engine.set_max_map_size(10_000);
+
+        engine.on_print(|text| info::info("Script".cyan(), String::from(text)));
+        engine.on_debug(|text, _, _| info::info("Script".cyan(), String::from(text)));
+
+        engine.register_fn("on_event", |event: &str, handler: &str| {
+            REGISTERING.lock().unwrap().push(Hook::Event(event.to_string(), handler.to_string()));
+        });
+        engine.register_fn("on_message", |msg_type: &str, handler: &str| {
+            REGISTERING.lock().unwrap().push(Hook::Message(msg_type.to_string(), handler.to_string()));
+        });
+
+        engine.register_fn("send_only", |ctx: NativeCallContext, user: &str, msg: Map| {
+            send(&ctx, Outgoing::Only(user.to_string(), rhai::format_map_as_json(&msg)));
+        });
+        engine.register_fn("send_all", |ctx: NativeCallContext, msg: Map| {
+            send(&ctx, Outgoing::All(rhai::format_map_as_json(&msg)));
+        });
+        engine.register_fn("kick", |ctx: NativeCallContext, user: &str, reason: &str| {
+            send(&ctx, Outgoing::Kick(user.to_string(), reason.to_string()));
+        });
+        engine.register_fn("users", || -> rhai::Array {
+            unsafe { USERS.iter().map(|user| Dynamic::from_map(to_map(&user_json(user)))).collect() }
+        });
+
+        engine
+    })
+}
+
+/**
+ * Put a message in the outbox of the call a script function runs in.
+ * Scripts can't send anything while they're loaded, there is no call then.
+ */
+fn send(ctx: &NativeCallContext, outgoing: Outgoing) {
+    if let Some(outbox) = ctx.tag().and_then(|tag| tag.clone().try_cast::<Outbox>()) {
+        outbox.lock().unwrap().push(outgoing);
+    }
+}
+
+/**
+ * Turn a json object into a script map.
+ */
+fn to_map(json: &Value) -> Map {
+    engine().parse_json(json.to_string(), true).unwrap_or_default()
+}
+
+/**
+ * What scripts get to know about a user.
+ */
+fn user_json(user: &FluxUser) -> Value {
+    json!({
+        "id": user.id,
+        "name": user.name,
+        "role": user.role.as_str()
+    })
+}
+
+/**
+ * Find the scripts in a directory and when they were last changed.
+ */
+fn scan(dir: &str) -> Vec<(PathBuf, SystemTime)> {
+    let Ok(entries) = fs::read_dir(dir) else { return Vec::new() };
+
+    let mut scripts: Vec<(PathBuf, SystemTime)> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "rhai"))
+        .filter_map(|path| Some((path.clone(), fs::metadata(&path).ok()?.modified().ok()?)))
+        .collect();
+
+    scripts.sort();
+    scripts
+}
+
+/**
+ * Compile and run the scripts of a directory, replacing the ones loaded before.
+ * Scripts that fail to compile are left out.
+ */
+pub fn load(dir: &str) {
+    let mut scripts = SCRIPTS.write().unwrap();
+    scripts.clear();
+
+    let scanned = scan(dir);
+    *SCANNED.lock().unwrap() = scanned.clone();
+
+    for (path, _) in scanned {
+        REGISTERING.lock().unwrap().clear();
+
+        let result = engine().compile_file(path.clone()).and_then(|ast| {
+            engine().run_ast(&ast)?;
+            Ok(ast)
+        });
+
+        match result {
+            Ok(ast) => {
+                let hooks = REGISTERING.lock().unwrap().drain(..).collect();
+                scripts.push(Script { path, ast, hooks });
+            }
+            Err(err) => info::info("Script".red(), format!("{} ({})", path.display(), err)),
+        }
+    }
+
+    info::info("Scripts".white(), format!("Loaded {} script(s) from {}", scripts.len(), dir));
+}
+
+/**
+ * Reload the scripts whenever a file in the directory changes.
+ */
+pub async fn watch(dir: String) {
+    loop {
+        tokio::time::sleep(RELOAD_INTERVAL).await;
+
+        if scan(&dir) != *SCANNED.lock().unwrap() {
+            load(&dir);
+        }
+    }
+}
+
+/**
+ * Call every script function registered for a hook.
+ * Returns the first error a function returned and what the functions want to send.
+ */
+fn call(matches: impl Fn(&Hook) -> Option<String>, args: Vec<Dynamic>) -> (Option<String>, Vec<Outgoing>) {
+    let scripts = SCRIPTS.read().unwrap();
+    let outbox: Outbox = Arc::default();
+    let mut err = None;
+
+    for script in scripts.iter() {
+        for function in script.hooks.iter().filter_map(&matches) {
+            let options = CallFnOptions::new().eval_ast(false).with_tag(outbox.clone());
+            let result = engine().call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &script.ast, &function, args.clone());
+
+            match result {
+                Ok(result) if result.is_string() => {
+                    err = err.or(result.into_string().ok());
+                }
+                Ok(_) => {}
+                Err(script_err) => {
+                    info::info("Script".red(), format!("{} {} ({})", script.path.display(), function, script_err));
+                    err = err.or(Some(String::from("Script failed")));
+                }
+            }
+        }
+    }
+
+    let outgoing = std::mem::take(&mut *outbox.lock().unwrap());
+    (err, outgoing)
+}
+
+/**
+ * Send everything the scripts wanted to send.
+ */
+async fn flush_outbox(outgoing: Vec<Outgoing>) {
+    for outgoing in outgoing {
+        match outgoing {
+            Outgoing::Only(user, content) => {
+                if let Some(addr) = get_user_id(user).map(|user| user.addr) {
+                    send_only(addr, content).await;
+                }
+            }
+            Outgoing::All(content) => send_all(SERVER_ADDR, content).await,
+            Outgoing::Kick(user, reason) => {
+                if let Some(addr) = get_user_id(user).map(|user| user.addr) {
+                    disconnect(addr, &reason).await;
+                }
+            }
+        }
+    }
+}
+
+/**
+ * Get the name scripts know an event by and what it's about.
+ */
+fn event_json(event: &Event) -> (&'static str, Value) {
+    match event {
+        Event::UserJoined { id, name } => ("user_joined", json!({ "id": id, "name": name })),
+        Event::UserLeft { id, name } => ("user_left", json!({ "id": id, "name": name })),
+        Event::UserRenamed { id, old, name } => ("user_renamed", json!({ "id": id, "old": old, "name": name })),
+        Event::ProfileUpdated { id } => ("profile_updated", json!({ "id": id })),
+        Event::ChatPosted { id, sender, target, parent, content } => (
+            "chat_posted",
+            json!({ "id": id, "sender": sender, "target": target, "parent": parent, "content": content }),
+        ),
+        Event::ChatEdited { id, by, content } => ("chat_edited", json!({ "id": id, "by": by, "content": content })),
+        Event::ChatDeleted { id, by } => ("chat_deleted", json!({ "id": id, "by": by })),
+        Event::FileShared { id, sender, name } => ("file_shared", json!({ "id": id, "sender": sender, "name": name })),
+        Event::OfferCreated { id, origin, target } => ("offer_created", json!({ "id": id, "origin": origin, "target": target })),
+        Event::OfferResolved { id, accepted } => ("offer_resolved", json!({ "id": id, "accepted": accepted })),
+        Event::SessionEstablished { offer, origin, target } => (
+            "session_established",
+            json!({ "offer": offer, "origin": origin, "target": target }),
+        ),
+    }
+}
+
+/**
+ * Pass the server events to the scripts that registered for them.
+ */
+pub async fn listen() {
+    let mut events = events::subscribe();
+
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(missed)) => {
+                info::info("Scripts".red(), format!("Missed {} event(s)", missed));
+                continue;
+            }
+            Err(_) => return,
+        };
+
+        let (name, event_json) = event_json(&event);
+        let event = Dynamic::from_map(to_map(&event_json));
+        let (_, outgoing) = call(|hook| match hook {
+            Hook::Event(event, function) if event == name => Some(function.clone()),
+            _ => None,
+        }, vec![event]);
+
+        flush_outbox(outgoing).await;
+    }
+}
+
+/**
+ * Handles the message types scripts registered with `on_message`.
+ */
+pub struct ScriptHandler;
+
+impl ScriptHandler {
+    /**
+     * Get the handler if a script handles a message type.
+     */
+    pub fn find(msg_type: &str) -> Option<ScriptHandler> {
+        let scripts = SCRIPTS.read().unwrap();
+        let handled = scripts.iter()
+            .flat_map(|script| script.hooks.iter())
+            .any(|hook| matches!(hook, Hook::Message(handled, _) if handled == msg_type));
+
+        handled.then_some(ScriptHandler)
+    }
+}
+
+impl MessageHandler for ScriptHandler {
+    fn handle(&self, json: Value, ctx: Context) -> HandlerFuture {
+        Box::pin(async move {
+            let msg_type = json["type"].as_str().unwrap_or_default().to_string();
+            let user = unsafe { USERS.iter().find(|user| user.addr == ctx.addr).map(user_json) };
+            let Some(user) = user else { return Some(String::from("User not authorized")) };
+
+            let args = vec![Dynamic::from_map(to_map(&user)), Dynamic::from_map(to_map(&json))];
+            let (err, outgoing) = call(|hook| match hook {
+                Hook::Message(handled, function) if *handled == msg_type => Some(function.clone()),
+                _ => None,
+            }, args);
+
+            flush_outbox(outgoing).await;
+            err
+        })
+    }
+}
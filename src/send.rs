@@ -1,9 +1,15 @@
-use std::net::SocketAddr;
-use futures_util::{stream::SplitSink, SinkExt, FutureExt, future};
-use tokio::{net::TcpStream, sync::MutexGuard};
-use tokio_tungstenite::{WebSocketStream, tungstenite::Message};
+use std::{net::{Ipv4Addr, SocketAddr, SocketAddrV4}, sync::Arc, time::Instant};
+use futures_util::{SinkExt, stream::SplitSink};
+use tokio::{net::TcpStream, sync::Mutex};
+use serde_json::Value;
+use tokio_tungstenite::{WebSocketStream, tungstenite::{Message, protocol::{CloseFrame, frame::coding::CloseCode}}};
 
-use crate::{USERS, info::get_user};
+use crate::{CLOSING, FluxUser, USERS, blocks, history, info::get_user, metrics, protocol, remove_user, resume::MAX_QUEUE};
+
+/**
+ * Sender address of messages from the server itself, it never matches a user.
+ */
+pub const SERVER_ADDR: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
 
 /**
  * Send a message to all clients expect the sender.
@@ -11,26 +17,104 @@ use crate::{USERS, info::get_user};
 pub async fn send_all(sender: SocketAddr, content: String) {
     unsafe {
         // Send the message to all users except the one who send it.
-        for user in USERS.iter() {
+        for user in USERS.iter_mut() {
             if user.addr != sender {
-                let _ = user.socket.lock().then(|mut socket| async {
-                    socket.send(Message::Text(String::clone(&content))).await.expect("Can send message");
-                    future::ok::<MutexGuard<SplitSink<WebSocketStream<TcpStream>, Message>>, MutexGuard<SplitSink<WebSocketStream<TcpStream>, Message>>>(socket)
-                }).await;
+                deliver(user, String::clone(&content)).await;
+            }
+        }
+    }
+}
+
+/**
+ * Send a message to all clients except the sender that agreed on using a feature.
+ */
+pub async fn send_feature(sender: SocketAddr, feature: &str, content: String) {
+    unsafe {
+        for user in USERS.iter_mut() {
+            if user.addr != sender && protocol::has_feature(user.addr, feature) {
+                deliver(user, String::clone(&content)).await;
             }
         }
     }
 }
 
+/**
+ * Send an update about a chat message to everyone who can see it except the sender,
+ * that's everyone for public messages or the other user for direct messages.
+ * Users that hide the messages of the author don't get it.
+ */
+pub async fn send_related(sender: SocketAddr, entry: &Value, content: String) {
+    let audience: Vec<SocketAddr> = unsafe {
+        USERS.iter()
+            .filter(|user| user.addr != sender && history::visible(entry, &user.id))
+            .filter(|user| !blocks::hides(&user.id, entry))
+            .filter(|user| protocol::has_feature(user.addr, protocol::entry_feature(entry)))
+            .map(|user| user.addr)
+            .collect()
+    };
+
+    for addr in audience {
+        send_only(addr, String::clone(&content)).await;
+    }
+}
+
 /**
  * Send a message to only one client.
  */
 pub async fn send_only(reciever: SocketAddr, content: String) {
-    let user = get_user(reciever);
+    let user = unsafe { USERS.iter_mut().find(|user| user.addr == reciever) };
+    let user = user.expect("Tried to send to user who doesn't exist");
 
     // Send the message to the reciever:
-    let _ = user.socket.lock().then(|mut socket| async {
-        socket.send(Message::Text(String::clone(&content))).await.expect("Can send message");
-        future::ok::<MutexGuard<SplitSink<WebSocketStream<TcpStream>, Message>>, MutexGuard<SplitSink<WebSocketStream<TcpStream>, Message>>>(socket)
-    }).await;
-}
\ No newline at end of file
+    deliver(user, content).await;
+}
+
+/**
+ * Send a message to a user, or queue it while they're reconnecting.
+ */
+async fn deliver(user: &mut FluxUser, content: String) {
+    if user.reconnecting.is_some() {
+        if user.queue.len() >= MAX_QUEUE {
+            user.queue.remove(0);
+        }
+        user.queue.push(content);
+        return;
+    }
+
+    send_socket(&user.socket, content).await;
+}
+
+/**
+ * Send a message directly to a socket, this also works for users who aren't logged in.
+ */
+pub async fn send_socket(socket: &Arc<Mutex<SplitSink<WebSocketStream<TcpStream>, Message>>>, content: String) {
+    let start = Instant::now();
+    let mut socket = socket.lock().await;
+    socket.send(Message::Text(String::clone(&content).into())).await.expect("Can send message");
+    metrics::send_latency(start.elapsed());
+    metrics::add(&metrics::BYTES_OUT, content.len());
+}
+
+/**
+ * Close the socket of a user and remove them from the system.
+ * Anything they send after this is ignored.
+ */
+pub async fn disconnect(addr: SocketAddr, reason: &str) {
+    let user = get_user(addr);
+
+    unsafe { CLOSING.push(addr); }
+    close_socket(&user.socket, CloseCode::Policy, reason).await;
+
+    remove_user(addr).await;
+}
+
+/**
+ * Send a close frame to a socket.
+ */
+pub async fn close_socket(socket: &Arc<Mutex<SplitSink<WebSocketStream<TcpStream>, Message>>>, code: CloseCode, reason: &str) {
+    // The socket might already be gone, so errors are ignored here.
+    let _ = socket.lock().await.send(Message::Close(Some(CloseFrame {
+        code,
+        reason: String::from(reason).into(),
+    }))).await;
+}
@@ -0,0 +1,231 @@
+use std::{env, io::Error, sync::Arc, time::Duration};
+
+use colored::*;
+use tokio::{net::TcpListener, task::JoinSet, time::timeout};
+
+use crate::{
+    accept_connection, accounts, admin, bans, blocks,
+    commands::{ChatCommand, ChatHandler, Commands},
+    flush_stores,
+    handlers::{MessageHandler, Registry},
+    history, info, metrics,
+    middleware::{self, Middleware, Pipeline, RateLimit, Scope},
+    names, offline, profiles, reads, resume,
+    roles::Permission,
+    shutdown, topic, SAVE_INTERVAL,
+};
+
+/**
+ * The flow server, build one with `Server::builder()`.
+ */
+pub struct Server {
+    addr: String,
+    pub(crate) handlers: Registry,
+    pub(crate) pipeline: Pipeline,
+}
+
+/**
+ * Configures a server before it runs.
+ */
+pub struct ServerBuilder {
+    addr: String,
+    handlers: Registry,
+    rate_limit: RateLimit,
+    pipeline: Pipeline,
+    commands: Commands,
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder {
+            addr: String::from("127.0.0.1:25656"),
+            handlers: Registry::builtin(),
+            rate_limit: RateLimit::default(),
+            pipeline: Pipeline::default(),
+            commands: Commands::builtin(),
+        }
+    }
+
+    /**
+     * Run the server until it's told to shut down.
+     */
+    pub async fn run(self) -> Result<(), Error> {
+        let server = Arc::new(self);
+
+        // Get the name policy from the environment.
+        if let Ok(policy) = env::var("FLOW_NAME_POLICY") {
+            let policy = names::NamePolicy::parse(&policy).expect("FLOW_NAME_POLICY must be \"reject\" or \"suffix\"");
+            unsafe { names::NAME_POLICY = policy; }
+        }
+
+        // Get the resume grace period (in seconds) from the environment.
+        if let Ok(grace) = env::var("FLOW_RESUME_GRACE") {
+            let grace = grace.parse().expect("FLOW_RESUME_GRACE must be a number of seconds");
+            unsafe { resume::RESUME_GRACE = Duration::from_secs(grace); }
+        }
+
+        bans::load();
+        history::load();
+        reads::load();
+        accounts::load();
+        offline::load();
+        blocks::load();
+        topic::load();
+        profiles::load();
+
+        // Keep flushing the persistent stores in the background.
+        tokio::spawn(async {
+            loop {
+                tokio::time::sleep(SAVE_INTERVAL).await;
+                flush_stores();
+            }
+        });
+
+        // Start the server by creating the TcpListener.
+        info::info("Startup".white(), String::from("Starting the server..."));
+        let try_socket = TcpListener::bind(&server.addr).await;
+        let listener = try_socket.expect("Failed to bind to address");
+        info::info("Started".white(), format!("Listening on: {}", server.addr));
+
+        // Serve the metrics on a local address.
+        let metrics_addr = env::var("FLOW_METRICS").unwrap_or_else(|_| "127.0.0.1:25657".to_string());
+        tokio::spawn(metrics::serve(metrics_addr));
+
+        // Load the scripts and keep them up to date.
+        #[cfg(feature = "scripting")]
+        {
+            let scripts_dir = env::var("FLOW_SCRIPTS").unwrap_or_else(|_| "scripts".to_string());
+            crate::scripting::load(&scripts_dir);
+            tokio::spawn(crate::scripting::watch(scripts_dir));
+            tokio::spawn(crate::scripting::listen());
+        }
+
+        // Serve the admin command channel on a local socket.
+        let admin_path = env::var("FLOW_ADMIN").unwrap_or_else(|_| "flow-admin.sock".to_string());
+        tokio::spawn(admin::serve(admin_path));
+
+        // Optional hint send to clients on shutdown, e.g. where or when to reconnect.
+        let reconnect = env::var("FLOW_RECONNECT").ok();
+
+        let shutdown_signal = shutdown::signal();
+        tokio::pin!(shutdown_signal);
+
+        // Keep waiting for new connections until we're told to stop.
+        let mut handlers = JoinSet::new();
+        loop {
+            tokio::select! {
+                conn = listener.accept() => match conn {
+                    // When a connection is made spawn a new thread for it.
+                    Ok((stream, _)) => { handlers.spawn(accept_connection(stream, Arc::clone(&server))); },
+                    Err(_) => break,
+                },
+                // Clean up the handlers of closed connections.
+                Some(_) = handlers.join_next(), if !handlers.is_empty() => {},
+                _ = &mut shutdown_signal => break,
+            }
+        }
+
+        // Stop accepting new connections.
+        drop(listener);
+        info::info("Shutdown".red(), String::from("Stopped accepting connections"));
+
+        shutdown::close_all(reconnect).await;
+
+        // Wait for the in-flight handlers to finish:
+        let drain = async { while handlers.join_next().await.is_some() {} };
+        if timeout(shutdown::SHUTDOWN_DEADLINE, drain).await.is_err() {
+            info::info("Shutdown".red(), format!("Aborting {} handler(s) after deadline", handlers.len()));
+            handlers.shutdown().await;
+        }
+
+        // Write what the handlers changed last:
+        flush_stores();
+
+        info::info("Stopped".white(), String::from("Server shut down"));
+        Ok(())
+    }
+}
+
+impl ServerBuilder {
+    /**
+     * The address to listen on.
+     */
+    pub fn addr(mut self, addr: impl Into<String>) -> ServerBuilder {
+        self.addr = addr.into();
+        self
+    }
+
+    /**
+     * Handle a message type, this can add new types or replace the handlers of built in ones.
+     */
+    pub fn handler(mut self, msg_type: &str, handler: impl MessageHandler + 'static) -> ServerBuilder {
+        self.handlers.register(msg_type, handler);
+        self
+    }
+
+    /**
+     * How many messages of a type a client can send in a burst, and how many per second after that.
+     * Types without their own limit share the default bucket of `RATE_BURST` and `RATE_PER_SECOND`.
+     */
+    pub fn rate_limit(mut self, msg_type: &str, burst: f64, per_second: f64) -> ServerBuilder {
+        self.rate_limit = self.rate_limit.limit(msg_type, burst, per_second);
+        self
+    }
+
+    /**
+     * Run a middleware for every message type, after the built in ones and the ones added before it.
+     */
+    pub fn middleware(mut self, middleware: impl Middleware + 'static) -> ServerBuilder {
+        self.pipeline.add(Scope::All, middleware);
+        self
+    }
+
+    /**
+     * Run a middleware only for some message types.
+     */
+    pub fn middleware_for(mut self, msg_types: &[&str], middleware: impl Middleware + 'static) -> ServerBuilder {
+        self.pipeline.add(Scope::Only(middleware::types(msg_types)), middleware);
+        self
+    }
+
+    /**
+     * Run a middleware for every message type except some.
+     */
+    pub fn middleware_except(mut self, msg_types: &[&str], middleware: impl Middleware + 'static) -> ServerBuilder {
+        self.pipeline.add(Scope::Except(middleware::types(msg_types)), middleware);
+        self
+    }
+
+    /**
+     * Add a slash command, this can add new commands or replace built in ones.
+     * Only users whose role has the permission can use it.
+     */
+    pub fn command(
+        mut self,
+        name: &str,
+        usage: &str,
+        description: &str,
+        permission: Option<Permission>,
+        handler: impl ChatCommand + 'static,
+    ) -> ServerBuilder {
+        self.commands.register(name, usage, description, permission, handler);
+        self
+    }
+
+    pub fn build(mut self) -> Server {
+        // Chat starting with a slash is a command for the server:
+        if let Some(chat) = self.handlers.remove("chat") {
+            self.handlers.register("chat", ChatHandler::new(self.commands, chat));
+        }
+
+        // The added middlewares run after the built in ones:
+        let mut pipeline = Pipeline::builtin(self.rate_limit);
+        pipeline.append(self.pipeline);
+
+        Server {
+            addr: self.addr,
+            handlers: self.handlers,
+            pipeline,
+        }
+    }
+}
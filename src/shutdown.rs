@@ -0,0 +1,75 @@
+use std::{sync::atomic::{AtomicBool, Ordering}, time::Duration};
+
+use colored::*;
+use futures_util::SinkExt;
+use serde_json::json;
+use tokio_tungstenite::tungstenite::{Message, protocol::{CloseFrame, frame::coding::CloseCode}};
+
+use crate::{info, OFFERS, SOCKETS, USERS};
+
+/**
+ * How long we wait for in-flight handlers before aborting them.
+ */
+pub const SHUTDOWN_DEADLINE: Duration = Duration::from_secs(10);
+
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/**
+ * Check if the server is shutting down, nobody can login anymore.
+ */
+pub fn started() -> bool {
+    STARTED.load(Ordering::Relaxed)
+}
+
+/**
+ * Resolves once the process receives SIGINT (Ctrl-C) or SIGTERM.
+ */
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("Can listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = terminate.recv() => {},
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.expect("Can listen for Ctrl-C");
+}
+
+/**
+ * Notify every open connection that the server is going down and close their sockets,
+ * this includes the ones that didn't login.
+ * { type: "shutdown", reconnect: "hint" | null }
+ */
+pub async fn close_all(reconnect: Option<String>) {
+    let shutdown_json = json!({
+        "type": "shutdown",
+        "reconnect": reconnect
+    });
+
+    STARTED.store(true, Ordering::Relaxed);
+
+    // Take the users out of the system so their handlers don't broadcast a leave.
+    let sockets = unsafe {
+        OFFERS.clear();
+        USERS.clear();
+        std::mem::take(&mut SOCKETS)
+    };
+
+    info::info("Shutdown".red(), format!("Closing {} connection(s)", sockets.len()));
+
+    for (_, socket) in sockets {
+        let mut socket = socket.lock().await;
+
+        // The socket might already be gone, so errors are ignored here.
+        let _ = socket.send(Message::Text(shutdown_json.to_string().into())).await;
+        let _ = socket.send(Message::Close(Some(CloseFrame {
+            code: CloseCode::Away,
+            reason: "Server shutting down".into(),
+        }))).await;
+    }
+}
@@ -0,0 +1,46 @@
+use std::{env, fs, path::PathBuf};
+
+use colored::*;
+use serde_json::Value;
+
+use crate::info;
+
+/**
+ * Get the path of a store file inside the data directory.
+ */
+pub fn path(name: &str) -> PathBuf {
+    let dir = env::var("FLOW_DATA").unwrap_or_else(|_| "data".to_string());
+    PathBuf::from(dir).join(format!("{}.json", name))
+}
+
+/**
+ * Load a store from disk, returns None if it doesn't exist (yet).
+ */
+pub fn load(name: &str) -> Option<Value> {
+    let path = path(name);
+    let content = fs::read_to_string(&path).ok()?;
+
+    match serde_json::from_str(&content) {
+        Ok(value) => Some(value),
+        Err(err) => {
+            info::info("Store".red(), format!("Failed to parse {} ({})", path.display(), err));
+            None
+        }
+    }
+}
+
+/**
+ * Write a store to disk, replacing the old file.
+ */
+pub fn save(name: &str, value: &Value) {
+    let path = path(name);
+    let tmp = path.with_extension("json.tmp");
+
+    let result = path.parent().map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&tmp, value.to_string()))
+        .and_then(|_| fs::rename(&tmp, &path));
+
+    if let Err(err) = result {
+        info::info("Store".red(), format!("Failed to write {} ({})", path.display(), err));
+    }
+}
@@ -0,0 +1,45 @@
+use serde_json::{json, Value};
+
+use crate::store;
+
+/**
+ * The topic of the server and the name of who set it.
+ */
+pub static mut TOPIC: Option<(String, String)> = None;
+
+/**
+ * Load the topic from the store.
+ */
+pub fn load() {
+    let Some(value) = store::load("topic") else { return };
+
+    if let (Some(topic), Some(by)) = (value["topic"].as_str(), value["by"].as_str()) {
+        unsafe { TOPIC = Some((topic.to_string(), by.to_string())); }
+    }
+}
+
+/**
+ * Change the topic, an empty topic clears it.
+ */
+pub fn set(topic: &str, by: &str) {
+    unsafe {
+        TOPIC = match topic.is_empty() {
+            true => None,
+            false => Some((topic.to_string(), by.to_string())),
+        };
+    }
+    store::save("topic", &json());
+}
+
+/**
+ * The topic as it's send to clients.
+ * { topic: "topic", by: "name" }
+ */
+pub fn json() -> Value {
+    unsafe {
+        match &TOPIC {
+            Some((topic, by)) => json!({ "topic": topic, "by": by }),
+            None => json!({ "topic": null, "by": null }),
+        }
+    }
+}
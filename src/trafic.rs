@@ -1,64 +1,170 @@
 use colored::*;
-use futures_util::stream::SplitSink;
+use futures_util::{stream::SplitSink, SinkExt};
 use serde_json::{json, Value};
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, sync::Arc, time::Instant};
 use tokio::{net::TcpStream, sync::Mutex};
 use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
 use uuid::Uuid;
 
 use crate::{
-    info::{self, dispose_offer, get_user, get_user_id, user_exists},
-    send::{send_all, send_only},
+    accounts, blocks,
+    events::{self, Event},
+    info::{self, dispose_offer, get_user, get_user_id, users_json},
+    history, messages, metrics, names, offline,
+    presence::{self, Presence, Status},
+    profiles,
+    protocol::{self, Negotiated, NEGOTIATED},
+    reads,
+    roles::Permission,
+    send::{send_all, send_feature, send_only, send_related, send_socket, SERVER_ADDR},
+    shutdown, topic,
     utils::{trim_ends},
-    FluxUser, Offer, OFFERS, USERS,
+    FluxUser, Offer, CLOSING, OFFERS, USERS,
 };
 
+/**
+ * Handle the hello message type.
+ * { type: "hello", protocols: [1], features: ["chat", "file", ...] }
+ * This is send before login to agree on a protocol version and features,
+ * if no common version exists the connection is closed.
+ * Messages of features that weren't agreed on are refused and the client doesn't get them either.
+ */
+pub async fn hello(
+    json: Value,
+    addr: SocketAddr,
+    socket: Arc<Mutex<SplitSink<WebSocketStream<TcpStream>, Message>>>,
+) -> Option<String> {
+    unsafe {
+        if USERS.iter().any(|user| user.addr == addr) {
+            return Some(String::from("Hello must be send before login"));
+        }
+        if NEGOTIATED.iter().any(|negotiated| negotiated.addr == addr) {
+            return Some(String::from("Hello can only be send once"));
+        }
+    }
+
+    let versions: Vec<u64> = match json["protocols"].as_array() {
+        Some(versions) => versions.iter().filter_map(|version| version.as_u64()).collect(),
+        None => return Some(String::from("Missing protocols field")),
+    };
+
+    // Refuse clients we can't talk to:
+    let protocol = match protocol::pick_version(&versions) {
+        Some(protocol) => protocol,
+        None => {
+            unsafe { CLOSING.push(addr); }
+            return Some(format!(
+                "Unsupported protocol {:?} (server supports {} to {})",
+                versions, protocol::MIN_PROTOCOL_VERSION, protocol::PROTOCOL_VERSION
+            ));
+        }
+    };
+
+    // Only keep the features both sides support:
+    let features: Vec<String> = json["features"].as_array().into_iter().flatten()
+        .filter_map(|feature| feature.as_str())
+        .filter(|feature| protocol::FEATURES.contains(feature))
+        .map(String::from)
+        .collect();
+
+    info::info(addr.to_string().white(), format!("@hello v{} {:?}", protocol, features));
+
+    let hello_json = json!({
+        "type": "hello",
+        "protocol": protocol,
+        "features": features,
+        "server": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION")
+    });
+
+    unsafe {
+        NEGOTIATED.push(Negotiated { addr, protocol, features });
+    }
+
+    send_socket(&socket, hello_json.to_string()).await;
+    None // Succes!
+}
+
 /**
  * Handle the login message type.
- * This will add the user to the users list.
+ * { type: "login", name: "name", password: "password" | null }
+ * { type: "login", api_token: "token" }
+ * This will add the user to the users list, with a password the name is registered to an account.
+ * Bots login with the api token they got from the admin channel.
  */
 pub async fn login(
     json: Value,
     addr: SocketAddr,
     socket: Arc<Mutex<SplitSink<WebSocketStream<TcpStream>, Message>>>,
 ) -> Option<String> {
-    if json["name"] != Value::Null {
+    if json["name"] != Value::Null || json["api_token"] != Value::Null {
         unsafe {
             // Check if the user isn't already logged in.
             if USERS.iter().all(|user| user.addr != addr) {
-                info::info(addr.to_string().white(), format!("@login {}", json["name"]));
+                // Check if the name follows the rules and isn't taken,
+                // registered users keep the same id every time they login.
+                let identity = match accounts::identify(&json).await {
+                    Ok(identity) => identity,
+                    Err(err) => return Some(err),
+                };
+                let (id, name) = (identity.id, identity.name);
+
+                // The users were already told the server is going down.
+                if shutdown::started() {
+                    return Some(String::from("Server is shutting down"));
+                }
 
-                // Send the new user an update with all online users:
+                info::info(addr.to_string().white(), format!("@login {}", name));
+
+                // Send the new user an update with all online users,
+                // the token can be used to resume the session after a reconnect:
+                let token = Uuid::new_v4().to_string();
                 let mut login_json = json!({
                     "type": "login",
-                    "users": []
+                    "users": users_json(addr),
+                    "unread": reads::unread_json(&id),
+                    "blocked": blocks::list(&id),
+                    "topic": topic::json()
                 });
-
-                let users = login_json["users"].as_array_mut().unwrap();
-                for user in USERS.iter() {
-                    users.push(json!({
-                        "id": user.id,
-                        "name": user.name
-                    }));
+                if protocol::has_feature(addr, "resume") {
+                    login_json["token"] = json!(token);
                 }
 
                 // Add the new user to the system.
                 USERS.push(FluxUser {
-                    id: Uuid::new_v4().to_string(),
-                    name: trim_ends(json["name"].to_string()),
+                    id,
+                    name,
                     addr,
                     socket,
+                    token,
+                    role: identity.role,
+                    bot: identity.bot,
+                    reconnecting: None,
+                    queue: Vec::new(),
+                    presence: Presence::new(),
+                    typing: None,
+                    sent: Vec::new(),
                 });
 
+                // Tell the user who they are, followed by who else is online:
+                let user = get_user(addr);
+                send_only(addr, protocol::welcome_json(user).to_string()).await;
                 send_only(addr, login_json.to_string()).await;
+                metrics::inc(&metrics::LOGINS);
+                events::emit(Event::UserJoined { id: user.id.clone(), name: user.name.clone() });
+
+                // Deliver everything that was send while the user was offline:
+                for content in offline::take(&user.id) {
+                    send_only(addr, content).await;
+                }
 
                 // Send an update to all other users that you've joined:
-                let user = get_user(addr);
                 let update_json = json!({
                     "type": "join",
                     "user": {
                         "id": user.id,
-                        "name": user.name
+                        "name": user.name,
+                        "bot": user.bot
                     }
                 });
 
@@ -74,31 +180,181 @@ pub async fn login(
     None // Succes!
 }
 
+/**
+ * Handle the resume message type.
+ * { type: "resume", token: "resume_token" }
+ * This rebinds a dropped session to the new socket, including its offers and queued messages.
+ */
+pub async fn resume(
+    json: Value,
+    addr: SocketAddr,
+    socket: Arc<Mutex<SplitSink<WebSocketStream<TcpStream>, Message>>>,
+) -> Option<String> {
+    let token = match json["token"].as_str() {
+        Some(token) => token,
+        None => return Some(String::from("Missing token field")),
+    };
+
+    if shutdown::started() {
+        return Some(String::from("Server is shutting down"));
+    }
+
+    unsafe {
+        // Check if the user isn't already logged in.
+        if USERS.iter().any(|user| user.addr == addr) {
+            return Some(String::from("User cannot login twice"));
+        }
+
+        let user = match USERS.iter_mut().find(|user| user.token == token) {
+            Some(user) => user,
+            None => return Some(String::from("Invalid or expired token")),
+        };
+
+        info::info(addr.to_string().white(), format!("@resume {}", user.name));
+
+        // The old socket might still be open if the server didn't notice the drop yet.
+        let was_reconnecting = user.reconnecting.is_some();
+        if !was_reconnecting {
+            let _ = user.socket.lock().await.close().await;
+        }
+
+        // Rebind the user to the new socket with a fresh token:
+        user.addr = addr;
+        user.socket = socket;
+        user.token = Uuid::new_v4().to_string();
+        user.reconnecting = None;
+        let queue = std::mem::take(&mut user.queue);
+
+        let resume_json = json!({
+            "type": "resume",
+            "id": user.id,
+            "token": user.token,
+            "users": users_json(addr),
+            "unread": reads::unread_json(&user.id)
+        });
+
+        send_only(addr, protocol::welcome_json(user).to_string()).await;
+        send_only(addr, resume_json.to_string()).await;
+
+        // Deliver everything that was send while the user was gone:
+        for content in queue {
+            send_only(addr, content).await;
+        }
+
+        // Let the other users know the user is back:
+        if was_reconnecting {
+            let user = get_user(addr);
+            let update_json = json!({
+                "type": "resumed",
+                "user": {
+                    "id": user.id,
+                    "name": user.name
+                }
+            });
+
+            send_feature(addr, "resume", update_json.to_string()).await;
+        }
+    }
+
+    None // Succes!
+}
+
 /**
  * Handle the chat message type.
- * This will send the recieved message to all connected users.
+ * { type: "chat", content: "message", target: "user_id" | null, parent: 1 | null, nonce: "client_nonce" }
+ * This will send the recieved message to all connected users, or only the target for direct messages.
  */
 pub async fn chat(json: Value, addr: SocketAddr) -> Option<String> {
-    // Check if the user is logged in:
-    if user_exists(addr) == false {
-        return Some(String::from("User not authorized"));
-    }
+    // The content is send as it is, the same as an edit:
+    if let Some(content) = json["content"].as_str() {
+        let user_id = get_user(addr).id.clone();
+
+        // Replies have to reference a message in the history:
+        let parent = match &json["parent"] {
+            Value::Null => None,
+            parent => match parent.as_u64() {
+                Some(parent) if history::get(parent).is_some_and(|entry| history::visible(entry, &user_id)) => Some(parent),
+                _ => return Some(String::from("Parent message not found")),
+            },
+        };
+
+        // Direct messages only go to their target, registered users get them when they're back online:
+        let (target, offline) = match &json["target"] {
+            Value::Null => (None, false),
+            Value::String(target) => match get_user_id(target.clone()) {
+                Some(target) if target.id == user_id => return Some(String::from("Cannot send a direct message to yourself")),
+                Some(target) => (Some(target.id.clone()), false),
+                None if accounts::get(target).is_some() && protocol::has_feature(addr, "offline") => (Some(target.clone()), true),
+                None => return Some(String::from("Target not found")),
+            },
+            _ => return Some(String::from("Target must be a user id")),
+        };
+
+        // Give the message an id, retries only get acknowledged again:
+        let stamp = match messages::stamp(addr, &json["nonce"]) {
+            Ok(stamp) => stamp,
+            Err(err) => return Some(err),
+        };
+        if stamp.duplicate {
+            messages::ack(addr, &stamp).await;
+            return None;
+        }
+
+        // Direct messages to a user that blocked the sender are dropped without telling them:
+        if target.as_ref().is_some_and(|target| blocks::blocks(target, &user_id)) {
+            messages::ack(addr, &stamp).await;
+            return None;
+        }
 
-    if json["content"] != Value::Null {
         info::user_info(addr, json["content"].to_string(), Color::Blue);
 
-        // Send the message to all other users:
+        // Sending a message ends the typing indicator.
+        unsafe {
+            if let Some(user) = USERS.iter_mut().find(|user| user.addr == addr) {
+                user.typing = None;
+            }
+        }
+
+        // Send the message to all other users, or only the target:
         let user = get_user(addr);
-        let msg_json = json!({
+        let mut msg_json = json!({
             "type": "chat",
+            "id": stamp.id,
+            "timestamp": stamp.timestamp,
             "sender": {
                 "id": user.id,
                 "name": user.name
             },
-            "content": trim_ends(json["content"].to_string())
+            "content": content
         });
+        if let Some(parent) = parent {
+            msg_json["parent"] = json!(parent);
+        }
+        if json["action"] == true {
+            msg_json["action"] = json!(true);
+        }
+        if let Some(target) = &target {
+            msg_json["target"] = json!(target);
+
+            if offline {
+                if let Err(err) = offline::push(target, msg_json.to_string(), None) {
+                    messages::unstamp(addr, &stamp);
+                    return Some(err);
+                }
+            }
+        }
+
+        history::push(&msg_json);
+        send_related(addr, &msg_json, msg_json.to_string()).await;
+        messages::ack(addr, &stamp).await;
 
-        send_all(addr, msg_json.to_string()).await;
+        events::emit(Event::ChatPosted {
+            id: stamp.id,
+            sender: user.id.clone(),
+            target,
+            parent,
+            content: content.to_string(),
+        });
     } else {
         return Some(String::from("Invalid (Message missing content)"));
     }
@@ -106,23 +362,384 @@ pub async fn chat(json: Value, addr: SocketAddr) -> Option<String> {
     None // Succes!
 }
 
+/**
+ * Handle the edit message type.
+ * { type: "edit", id: 1, content: "new content", nonce: "client_nonce" }
+ * Only the original sender can edit their chat message, like chat the edit gets acknowledged.
+ */
+pub async fn edit(json: Value, addr: SocketAddr) -> Option<String> {
+    let (id, content) = match (json["id"].as_u64(), json["content"].as_str()) {
+        (Some(id), Some(content)) => (id, content.to_string()),
+        _ => return Some(String::from("Invalid (Message missing id or content)")),
+    };
+
+    let user = get_user(addr);
+    match history::get(id) {
+        Some(entry) if entry["sender"]["id"] != user.id.as_str() => return Some(String::from("Access declined")),
+        Some(entry) if entry["deleted"] != Value::Null => return Some(String::from("Message was deleted")),
+        Some(_) => {}
+        None => return Some(String::from("Message not found")),
+    }
+
+    // Retries only get acknowledged again:
+    let stamp = match messages::stamp(addr, &json["nonce"]) {
+        Ok(stamp) => stamp,
+        Err(err) => return Some(err),
+    };
+    if stamp.duplicate {
+        messages::ack(addr, &stamp).await;
+        return None;
+    }
+
+    info::user_info(addr, format!("Edited #{} {:?}", id, content), Color::Blue);
+    let edited = history::edit(id, content.clone(), &user.id, stamp.timestamp);
+    let (Some(edited), Some(entry)) = (edited, history::get(id).cloned()) else {
+        messages::unstamp(addr, &stamp);
+        return Some(String::from("Message not found"));
+    };
+
+    // Let the other users know the message changed:
+    let edit_json = json!({
+        "type": "edit",
+        "id": id,
+        "edited": edited,
+        "content": content
+    });
+
+    send_related(addr, &entry, edit_json.to_string()).await;
+    messages::ack(addr, &stamp).await;
+    events::emit(Event::ChatEdited { id, by: user.id.clone(), content });
+
+    None // Succes!
+}
+
+/**
+ * Handle the delete message type.
+ * { type: "delete", id: 1, nonce: "client_nonce" }
+ * Only the original sender can delete their chat message, like chat the delete gets acknowledged.
+ */
+pub async fn delete(json: Value, addr: SocketAddr) -> Option<String> {
+    let id = match json["id"].as_u64() {
+        Some(id) => id,
+        None => return Some(String::from("Invalid (Message missing id)")),
+    };
+
+    // Moderators can delete the messages of others:
+    let user = get_user(addr);
+    let moderate = user.role.can(Permission::Moderate);
+    match history::get(id) {
+        Some(entry) if !history::visible(entry, &user.id) => return Some(String::from("Message not found")),
+        Some(entry) if entry["sender"]["id"] != user.id.as_str() && !moderate => return Some(String::from("Access declined")),
+        Some(entry) if entry["deleted"] != Value::Null => return Some(String::from("Message was already deleted")),
+        Some(_) => {}
+        None => return Some(String::from("Message not found")),
+    }
+
+    // Retries only get acknowledged again:
+    let stamp = match messages::stamp(addr, &json["nonce"]) {
+        Ok(stamp) => stamp,
+        Err(err) => return Some(err),
+    };
+    if stamp.duplicate {
+        messages::ack(addr, &stamp).await;
+        return None;
+    }
+
+    info::user_info(addr, format!("Deleted #{}", id), Color::Blue);
+    let deleted = history::delete(id, &user.id, stamp.timestamp);
+    let (Some(deleted), Some(entry)) = (deleted, history::get(id).cloned()) else {
+        messages::unstamp(addr, &stamp);
+        return Some(String::from("Message not found"));
+    };
+
+    // Let the other users know the message is gone:
+    let delete_json = json!({
+        "type": "delete",
+        "id": id,
+        "deleted": deleted
+    });
+
+    send_related(addr, &entry, delete_json.to_string()).await;
+    messages::ack(addr, &stamp).await;
+    events::emit(Event::ChatDeleted { id, by: user.id.clone() });
+
+    None // Succes!
+}
+
+/**
+ * Handle the react message type.
+ * { type: "react", id: 1, emoji: "👍", remove: false, nonce: "client_nonce" }
+ * This adds or removes the reaction of the user to a chat message, like chat the reaction gets acknowledged.
+ */
+pub async fn react(json: Value, addr: SocketAddr) -> Option<String> {
+    let (id, emoji) = match (json["id"].as_u64(), json["emoji"].as_str()) {
+        (Some(id), Some(emoji)) => (id, emoji),
+        _ => return Some(String::from("Invalid (Message missing id or emoji)")),
+    };
+    let remove = json["remove"].as_bool().unwrap_or(false);
+
+    // Reactions are short and can't be plain text:
+    if emoji.is_empty() || emoji.chars().count() > 8 || emoji.chars().any(|c| c.is_ascii_alphanumeric() || c.is_whitespace() || c.is_control()) {
+        return Some(String::from("Invalid emoji"));
+    }
+
+    let user = get_user(addr);
+    let entry = match history::get(id) {
+        Some(entry) if !history::visible(entry, &user.id) => return Some(String::from("Message not found")),
+        Some(entry) if entry["deleted"] != Value::Null => return Some(String::from("Message was deleted")),
+        Some(entry) => entry.clone(),
+        None => return Some(String::from("Message not found")),
+    };
+
+    // Retries only get acknowledged again:
+    let stamp = match messages::stamp(addr, &json["nonce"]) {
+        Ok(stamp) => stamp,
+        Err(err) => return Some(err),
+    };
+    if stamp.duplicate {
+        messages::ack(addr, &stamp).await;
+        return None;
+    }
+
+    let count = match history::react(id, emoji, &user.id, remove) {
+        Ok(count) => count,
+        Err(err) => {
+            messages::unstamp(addr, &stamp);
+            return Some(err);
+        }
+    };
+
+    info::user_info(addr, format!("Reacted {} to #{}", emoji, id), Color::Blue);
+
+    // Let the other users know about the reaction:
+    let react_json = json!({
+        "type": "react",
+        "id": id,
+        "emoji": emoji,
+        "user": {
+            "id": user.id,
+            "name": user.name
+        },
+        "remove": remove,
+        "count": count,
+        "timestamp": stamp.timestamp
+    });
+
+    send_related(addr, &entry, react_json.to_string()).await;
+    messages::ack(addr, &stamp).await;
+
+    None // Succes!
+}
+
+/**
+ * Handle the read message type.
+ * { type: "read", id: 1 }
+ * This marks everything up to a message as read in its conversation,
+ * the sender of a direct message gets a receipt.
+ */
+pub async fn read(json: Value, addr: SocketAddr) -> Option<String> {
+    let id = match json["id"].as_u64() {
+        Some(id) => id,
+        None => return Some(String::from("Invalid (Message missing id)")),
+    };
+
+    let user = get_user(addr);
+    let entry = match history::get(id) {
+        Some(entry) if history::visible(entry, &user.id) => entry.clone(),
+        _ => return Some(String::from("Message not found")),
+    };
+
+    let conversation = history::conversation(&entry, &user.id);
+    if !reads::mark(&user.id, &conversation, id) {
+        return None;
+    }
+
+    // Let the sender of a direct message know it was read:
+    if entry["target"] == user.id.as_str() {
+        if let Some(sender) = entry["sender"]["id"].as_str().and_then(|id| get_user_id(id.to_string())) {
+            let read_json = json!({
+                "type": "read",
+                "user": {
+                    "id": user.id,
+                    "name": user.name
+                },
+                "id": id
+            });
+
+            if protocol::has_feature(sender.addr, "receipts") {
+                send_only(sender.addr, read_json.to_string()).await;
+            }
+        }
+    }
+
+    None // Succes!
+}
+
+/**
+ * Handle the history message type.
+ * { type: "history", before: 1 | null, parent: 1 | null, limit: 50 }
+ * This sends the user the most recent chat messages before an id, or the replies to a parent message.
+ */
+pub async fn history(json: Value, addr: SocketAddr) -> Option<String> {
+    let before = json["before"].as_u64();
+    let parent = json["parent"].as_u64();
+    let limit = json["limit"].as_u64().map_or(history::MAX_REPLAY, |limit| limit as usize);
+
+    let history_json = json!({
+        "type": "history",
+        "parent": parent,
+        "messages": history::replay(&get_user(addr).id, before, parent, limit)
+    });
+
+    send_only(addr, history_json.to_string()).await;
+
+    None // Succes!
+}
+
+/**
+ * Handle the presence message type.
+ * { type: "presence", status: "online" | "away" | "busy" | "invisible", text: "custom status" }
+ * This updates the presence of the user and lets all other users know.
+ */
+pub async fn presence(json: Value, addr: SocketAddr) -> Option<String> {
+    let status = match json["status"].as_str().and_then(Status::parse) {
+        Some(status) => status,
+        None => return Some(String::from("Invalid status (online, away, busy or invisible)")),
+    };
+
+    let text = match &json["text"] {
+        Value::Null => None,
+        Value::String(text) if text.chars().count() > presence::MAX_STATUS_LENGTH => {
+            return Some(format!("Status text is longer than {} characters", presence::MAX_STATUS_LENGTH));
+        }
+        Value::String(text) if text.chars().any(char::is_control) => {
+            return Some(String::from("Status text contains control characters"));
+        }
+        Value::String(text) => Some(text.trim().to_string()).filter(|text| !text.is_empty()),
+        _ => return Some(String::from("Status text must be a string")),
+    };
+
+    let user = unsafe { USERS.iter_mut().find(|user| user.addr == addr).unwrap() };
+    let was_public = user.presence.public_json();
+    user.presence = Presence { status, text };
+
+    info::user_info(addr, format!("Presence {:?}", status), Color::Cyan);
+
+    // Only let the other users know if they can actually see the difference:
+    let presence_json = user.presence.public_json();
+    if presence_json != was_public {
+        let update_json = json!({
+            "type": "presence",
+            "user": {
+                "id": user.id,
+                "name": user.name
+            },
+            "presence": presence_json
+        });
+
+        send_feature(addr, "presence", update_json.to_string()).await;
+    }
+
+    None // Succes!
+}
+
+/**
+ * Handle the typing message type.
+ * { type: "typing", typing: true | false, target: "user_id" | null }
+ * Typing indicators stop by themselves if they aren't refreshed in time.
+ */
+pub async fn typing(json: Value, addr: SocketAddr) -> Option<String> {
+    let typing = match json["typing"].as_bool() {
+        Some(typing) => typing,
+        None => return Some(String::from("Invalid (Message missing typing)")),
+    };
+
+    let target = match &json["target"] {
+        Value::Null => None,
+        Value::String(target) if get_user_id(target.clone()).is_some() => Some(target.clone()),
+        _ => return Some(String::from("Target not found")),
+    };
+
+    let user = unsafe { USERS.iter_mut().find(|user| user.addr == addr).unwrap() };
+
+    // Invisible users don't give themselves away by typing.
+    if user.presence.status == Status::Invisible {
+        return None;
+    }
+
+    // Refreshing an indicator only resets its timer, so the others aren't flooded.
+    let refresh = typing && user.typing.as_ref().is_some_and(|(_, old)| *old == target);
+    let was_typing = user.typing.take();
+
+    let typing_json = |typing: bool| json!({
+        "type": "typing",
+        "user": {
+            "id": user.id,
+            "name": user.name
+        },
+        "typing": typing
+    });
+
+    // The old indicator stops where it was shown, when typing stops or moves to another target:
+    if let Some((_, old)) = was_typing.filter(|_| !refresh) {
+        presence::send_typing(addr, old.as_deref(), &typing_json(false)).await;
+    }
+
+    if typing {
+        let since = Instant::now();
+        if !refresh {
+            presence::send_typing(addr, target.as_deref(), &typing_json(true)).await;
+        }
+
+        user.typing = Some((since, target));
+        tokio::spawn(presence::expire_typing(user.id.clone(), since));
+    }
+
+    None // Succes!
+}
+
 /**
  * Handle the file message type.
  * This will send the recieved file to all connected users.
+ * { type: "file", name: "me.png", content: "base64", avatar: true } sets the avatar of the sender instead.
  */
 pub async fn file(json: Value, addr: SocketAddr) -> Option<String> {
-    // Check if the user is logged in:
-    if user_exists(addr) == false {
-        return Some(String::from("User not authorized"));
-    }
-
     if json["content"] != Value::Null && json["name"] != Value::Null {
+        // Avatars go into the profile of the sender instead of being shared:
+        if json["avatar"] == true {
+            let user = get_user(addr);
+            if accounts::get(&user.id).is_none() {
+                return Some(String::from("Only registered users have a profile"));
+            }
+
+            let name = json["name"].as_str().unwrap_or_default();
+            if let Err(err) = profiles::set_avatar(&user.id, name, json["content"].as_str().unwrap_or_default()) {
+                return Some(err);
+            }
+
+            info::user_info(addr, format!("Changed their avatar to {:?}", name), Color::Yellow);
+            profile_changed(user).await;
+            return None;
+        }
+
+        // Give the file an id, retries only get acknowledged again:
+        let stamp = match messages::stamp(addr, &json["nonce"]) {
+            Ok(stamp) => stamp,
+            Err(err) => return Some(err),
+        };
+        if stamp.duplicate {
+            messages::ack(addr, &stamp).await;
+            return None;
+        }
+
         info::user_info(addr, json["name"].to_string(), Color::Blue);
 
-        // Send the file to all other users:
+        // Send the file to all other users that didn't block the sender:
         let user = get_user(addr);
         let msg_json = json!({
             "type": "file",
+            "id": stamp.id,
+            "timestamp": stamp.timestamp,
             "sender": {
                 "id": user.id,
                 "name": user.name
@@ -131,7 +748,14 @@ pub async fn file(json: Value, addr: SocketAddr) -> Option<String> {
             "content": trim_ends(json["content"].to_string())
         });
 
-        send_all(addr, msg_json.to_string()).await;
+        send_related(addr, &msg_json, msg_json.to_string()).await;
+        messages::ack(addr, &stamp).await;
+
+        events::emit(Event::FileShared {
+            id: stamp.id,
+            sender: user.id.clone(),
+            name: trim_ends(json["name"].to_string()),
+        });
     } else {
         return Some(String::from("Invalid (Message missing content or name)"));
     }
@@ -145,13 +769,14 @@ pub async fn file(json: Value, addr: SocketAddr) -> Option<String> {
  * This is called when a user wishes to open a peer connection with another user.
  */
 pub async fn request(json: Value, addr: SocketAddr) -> Option<String> {
-    // Check if the user is logged in:
-    if user_exists(addr) == false {
-        return Some(String::from("User not authorized"));
-    }
-
     if json["target"] != Value::Null {
         unsafe {
+            // Requests to a user that blocked the origin are dropped without telling them:
+            if blocks::blocks(&trim_ends(json["target"].to_string()), &get_user(addr).id) {
+                info::user_info(addr, String::from("Request dropped (blocked)"), Color::Magenta);
+                return None;
+            }
+
             // Attempt to find the target user.
             let search_attempt = get_user_id(trim_ends(json["target"].to_string()));
 
@@ -180,8 +805,41 @@ pub async fn request(json: Value, addr: SocketAddr) -> Option<String> {
                     });
 
                     send_only(target.addr, offer_json.to_string()).await;
+                    events::emit(Event::OfferCreated { id: offer_id, origin: origin.id.clone(), target: target.id.clone() });
+                }
+                None => {
+                    // Registered users get the request when they're back online:
+                    let target_id = trim_ends(json["target"].to_string());
+                    let target = match accounts::get(&target_id).filter(|_| protocol::has_feature(addr, "offline")) {
+                        Some(target) => target,
+                        None => return Some(String::from("Request Invalid (target not found)")),
+                    };
+
+                    info::user_info(
+                        addr,
+                        format!("Queued request for {}", target.name),
+                        Color::Magenta,
+                    );
+
+                    let origin = get_user(addr);
+                    let offer_id = Uuid::new_v4().to_string();
+                    let offer_json = json!({
+                        "type": "offer",
+                        "origin": origin.id.clone(),
+                        "id": offer_id
+                    });
+
+                    if let Err(err) = offline::push(&target.id, offer_json.to_string(), Some(offer_id.clone())) {
+                        return Some(err);
+                    }
+
+                    OFFERS.push(Offer {
+                        origin: origin.id.clone(),
+                        target: target.id.clone(),
+                        id: offer_id.clone(),
+                    });
+                    events::emit(Event::OfferCreated { id: offer_id, origin: origin.id.clone(), target: target.id.clone() });
                 }
-                None => return Some(String::from("Request Invalid (target not found)"))
             }
         }
     }
@@ -194,11 +852,6 @@ pub async fn request(json: Value, addr: SocketAddr) -> Option<String> {
  * This is called when a user wants to accept or decline an offer.
  */
 pub async fn offer(json: Value, addr: SocketAddr) -> Option<String> {
-    // Check if the user is logged in:
-    if user_exists(addr) == false {
-        return Some(String::from("User not authorized"));
-    }
-
     if json["accept"] != Value::Null && json["id"] != Value::Null {
         let accept = json["accept"].as_bool();
         let id = json["id"].as_str();
@@ -221,6 +874,7 @@ pub async fn offer(json: Value, addr: SocketAddr) -> Option<String> {
                                     }
 
                                     if accept {
+                                        metrics::inc(&metrics::OFFERS_ACCEPTED);
                                         info::user_info(
                                             addr,
                                             format!("Accepted request from {}", origin.name),
@@ -236,7 +890,9 @@ pub async fn offer(json: Value, addr: SocketAddr) -> Option<String> {
 
                                         send_only(target.addr, confirm_json.to_string()).await;
                                         send_only(origin.addr, confirm_json.to_string()).await;
+                                        events::emit(Event::OfferResolved { id: offer.id.clone(), accepted: true });
                                     } else {
+                                        metrics::inc(&metrics::OFFERS_DECLINED);
                                         info::user_info(
                                             addr,
                                             format!(
@@ -256,6 +912,8 @@ pub async fn offer(json: Value, addr: SocketAddr) -> Option<String> {
                                         send_only(target.addr, confirm_json.to_string()).await;
                                         send_only(origin.addr, confirm_json.to_string()).await;
 
+                                        events::emit(Event::OfferResolved { id: offer.id.clone(), accepted: false });
+
                                         // Remove the offer from the offers.
                                         dispose_offer(offer.id.clone());
                                     }
@@ -282,39 +940,34 @@ pub async fn offer(json: Value, addr: SocketAddr) -> Option<String> {
  * { type: "session", id: "offer_id", port: "punched_port" }
  */
 pub async fn session(json: Value, addr: SocketAddr) -> Option<String> {
-    // Check if the user is logged in:
-    if user_exists(addr) == false {
-        return Some(String::from("User not authorized"));
-    }
-
     if json["offer"] != Value::Null && json["port"] != Value::Null {
 
         // Get the port and id from the json:
         let port = json["port"].as_u64();
         let offer_id = json["offer"].as_str();
 
-        if let None = offer_id { return Some(String::from("Offer is invalid")); } 
+        if offer_id.is_none() { return Some(String::from("Offer is invalid")); } 
         let offer_id = offer_id.unwrap();
-        if let None = port { return Some(String::from("Port is invalid")); } 
+        if port.is_none() { return Some(String::from("Port is invalid")); } 
         let port = port.unwrap().to_string();
 
         // Get the offer from offers list:
         let offer;
         unsafe { offer = OFFERS.iter().find(|&offer| offer.id == offer_id); }
 
-        if let None = offer { return Some(String::from("Offer doesn't exist")); }
+        if offer.is_none() { return Some(String::from("Offer doesn't exist")); }
         let offer = offer.unwrap();
 
         // Get the target and origin:
         let target = get_user_id(offer.target.clone());
         let origin = get_user_id(offer.origin.clone());
 
-        if let None = target { return Some(String::from("Target doesn't exist")); } 
+        if target.is_none() { return Some(String::from("Target doesn't exist")); } 
         let target = target.unwrap();
-        if let None = origin { return Some(String::from("Origin doesn't exist")); } 
+        if origin.is_none() { return Some(String::from("Origin doesn't exist")); } 
         let origin = origin.unwrap();
 
-        let peer_addr = format!("{}:{}", addr.ip().to_string(), port);
+        let peer_addr = format!("{}:{}", addr.ip(), port);
 
         // Create the message for the origin:
         let peer_json = json!({
@@ -323,20 +976,189 @@ pub async fn session(json: Value, addr: SocketAddr) -> Option<String> {
             "offer": offer.id.clone()
         });
 
+        let established = Event::SessionEstablished {
+            offer: offer.id.clone(),
+            origin: origin.id.clone(),
+            target: target.id.clone(),
+        };
+
         if target.addr == addr {
             send_only(origin.addr, peer_json.to_string()).await;
             dispose_offer(offer.id.clone());
+            events::emit(established);
             return None;
         }
 
         if origin.addr == addr {
             send_only(target.addr, peer_json.to_string()).await;
             dispose_offer(offer.id.clone());
+            events::emit(established);
             return None;
         }
 
-        return Some(String::from("Access declined"));
+        Some(String::from("Access declined"))
     } else {
-        return Some(String::from("Missing id or port"));
+        Some(String::from("Missing id or port"))
+    }
+}
+
+/**
+ * Handle the rename message type.
+ * { type: "rename", name: "new name" }
+ * The new name follows the same rules as login, including the name policy for names that are taken.
+ * Registered users keep it the next time they login.
+ * Everyone gets { type: "rename", user: { id: "user_id", name: "new name", bot: false }, old: "old name" }
+ */
+pub async fn rename(json: Value, addr: SocketAddr) -> Option<String> {
+    let user = get_user(addr);
+    let name = match accounts::requested_name(&json["name"]) {
+        // Users may change how their own name is written, names others use are handled like at login:
+        Ok(name) if names::skeleton(&name) == names::skeleton(&user.name) => name,
+        Ok(name) => match names::make_unique(name) {
+            Ok(name) => name,
+            Err(err) => return Some(err),
+        },
+        Err(err) => return Some(err),
+    };
+
+    if accounts::find(&name).is_some_and(|account| account.id != user.id) {
+        return Some(String::from("Name is registered"));
+    }
+    if name == user.name {
+        return None;
+    }
+
+    let old = user.name.clone();
+    unsafe {
+        if let Some(user) = USERS.iter_mut().find(|user| user.addr == addr) {
+            user.name = name.clone();
+        }
     }
-}
\ No newline at end of file
+    accounts::rename(&user.id, &name);
+    info::user_info(addr, format!("Renamed from {}", old), Color::Yellow);
+
+    let rename_json = json!({
+        "type": "rename",
+        "user": {
+            "id": user.id,
+            "name": name,
+            "bot": user.bot
+        },
+        "old": old
+    });
+
+    send_feature(SERVER_ADDR, "rename", rename_json.to_string()).await;
+    events::emit(Event::UserRenamed { id: user.id.clone(), old, name });
+
+    None // Succes!
+}
+
+/**
+ * Handle the profile message type.
+ * { type: "profile", id: "user_id" } gets the profile of a user, online or registered.
+ * { type: "profile", update: { display_name: "name", bio: "about me", status: "status", timezone: "UTC" } } changes your own,
+ * fields that are left out stay the same and null clears them.
+ */
+pub async fn profile(json: Value, addr: SocketAddr) -> Option<String> {
+    let user = get_user(addr);
+
+    if json["update"] != Value::Null {
+        if accounts::get(&user.id).is_none() {
+            return Some(String::from("Only registered users have a profile"));
+        }
+        if let Err(err) = profiles::update(&user.id, &json["update"]) {
+            return Some(err);
+        }
+
+        info::user_info(addr, String::from("Updated their profile"), Color::Yellow);
+        profile_changed(user).await;
+        return None;
+    }
+
+    let id = match json["id"].as_str() {
+        Some(id) => id,
+        None => return Some(String::from("Missing id or update")),
+    };
+
+    let name = match (get_user_id(id.to_string()), accounts::get(id)) {
+        (Some(user), _) => user.name.clone(),
+        (None, Some(account)) => account.name.clone(),
+        (None, None) => return Some(String::from("User not found")),
+    };
+
+    send_only(addr, profile_json(id, &name, true).to_string()).await;
+    None // Succes!
+}
+
+/**
+ * Create the message with the profile of a user.
+ * { type: "profile", id: "user_id", name: "name", profile: { display_name, avatar: { name, content } | null, bio, status, timezone } }
+ */
+fn profile_json(id: &str, name: &str, avatar_content: bool) -> Value {
+    json!({
+        "type": "profile",
+        "id": id,
+        "name": name,
+        "profile": profiles::json(id, avatar_content)
+    })
+}
+
+/**
+ * Send everyone the new profile of a user, without the avatar content to keep it small.
+ */
+async fn profile_changed(user: &FluxUser) {
+    send_feature(SERVER_ADDR, "profiles", profile_json(&user.id, &user.name, false).to_string()).await;
+    events::emit(Event::ProfileUpdated { id: user.id.clone() });
+}
+
+/**
+ * Handle the block message type.
+ * { type: "block", target: "user_id", remove: false, chat: false }
+ * Blocked users can't send requests or direct messages to the user, with chat their public chat is filtered out too.
+ * Blocks of guests only last until they leave.
+ */
+pub async fn block(json: Value, addr: SocketAddr) -> Option<String> {
+    let target = match json["target"].as_str() {
+        Some(target) => target.to_string(),
+        None => return Some(String::from("Invalid (Message missing target)")),
+    };
+    let remove = json["remove"].as_bool().unwrap_or(false);
+    let chat = json["chat"].as_bool().unwrap_or(false);
+
+    let user = get_user(addr);
+    if target == user.id {
+        return Some(String::from("Cannot block yourself"));
+    }
+
+    if remove {
+        if !blocks::unblock(&user.id, &target) {
+            return Some(String::from("User is not blocked"));
+        }
+        info::user_info(addr, format!("Unblocked {}", target), Color::Yellow);
+    } else {
+        if get_user_id(target.clone()).is_none() && accounts::get(&target).is_none() {
+            return Some(String::from("Target not found"));
+        }
+
+        match blocks::block(&user.id, &target, chat) {
+            Ok(true) => {}
+            Ok(false) => return Some(String::from("User is already blocked")),
+            Err(err) => return Some(err),
+        }
+        info::user_info(addr, format!("Blocked {}", target), Color::Yellow);
+
+        // Cancel the pending requests of the blocked user:
+        unsafe { OFFERS.retain(|offer| offer.origin != target || offer.target != user.id); }
+    }
+
+    let block_json = json!({
+        "type": "block",
+        "target": target,
+        "remove": remove,
+        "chat": chat && !remove
+    });
+
+    send_only(addr, block_json.to_string()).await;
+
+    None // Succes!
+}
@@ -0,0 +1,219 @@
+//! Runs a server and checks that every message it sends survives a round trip through the shared protocol types.
+
+use std::{env, net::TcpListener, time::Duration};
+
+use flow::Server;
+use flow_protocol::{ServerMessage, FEATURES, PROTOCOL_VERSION};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpStream, UnixStream},
+    time::timeout,
+};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+/**
+ * Check that every field the server send is still there after decoding and encoding the message.
+ * Fields that are null or left out by the server may show up as their default, nested messages like the chats in
+ * history keep their type which the protocol types only have as the tag of the outer message.
+ */
+fn covers(decoded: &Value, sent: &Value, path: &str) {
+    match (decoded, sent) {
+        (_, Value::Null) => {}
+        (Value::Object(decoded), Value::Object(sent)) => {
+            for (key, value) in sent {
+                if key == "type" && path.contains(['.', '[']) {
+                    continue;
+                }
+                covers(decoded.get(key).unwrap_or(&Value::Null), value, &format!("{}.{}", path, key));
+            }
+        }
+        (Value::Array(decoded), Value::Array(sent)) => {
+            assert_eq!(decoded.len(), sent.len(), "{} changed length", path);
+            for (index, (decoded, sent)) in decoded.iter().zip(sent).enumerate() {
+                covers(decoded, sent, &format!("{}[{}]", path, index));
+            }
+        }
+        (decoded, sent) => assert_eq!(decoded, sent, "{} changed", path),
+    }
+}
+
+/**
+ * A websocket connection that checks every message it gets.
+ */
+struct Conn {
+    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl Conn {
+    async fn connect(url: &str) -> Conn {
+        let (stream, _) = connect_async(url).await.expect("Server accepts connections");
+        let mut conn = Conn { stream };
+        conn.send(json!({ "type": "hello", "protocols": [PROTOCOL_VERSION], "features": FEATURES })).await;
+        conn.expect("hello").await;
+        conn
+    }
+
+    async fn send(&mut self, json: Value) {
+        self.stream.send(Message::Text(json.to_string().into())).await.expect("Message is send");
+    }
+
+    /**
+     * Wait for a message of a type, the messages before it are checked too.
+     */
+    async fn expect(&mut self, msg_type: &str) -> Value {
+        loop {
+            let message = timeout(Duration::from_secs(5), self.stream.next()).await
+                .unwrap_or_else(|_| panic!("Timed out waiting for {}", msg_type))
+                .unwrap_or_else(|| panic!("Connection closed waiting for {}", msg_type))
+                .expect("Connection is fine");
+
+            let Message::Text(text) = message else { continue };
+            let sent: Value = serde_json::from_str(&text).expect("Server sends json");
+
+            let decoded: ServerMessage = serde_json::from_value(sent.clone())
+                .unwrap_or_else(|err| panic!("Couldn't decode {} ({})", sent, err));
+            assert!(!matches!(decoded, ServerMessage::Unknown), "Unknown message {}", sent);
+            covers(&serde_json::to_value(&decoded).unwrap(), &sent, sent["type"].as_str().unwrap_or_default());
+
+            if sent["type"] == msg_type {
+                return sent;
+            }
+        }
+    }
+}
+
+/**
+ * Send a command to the admin channel and get its output.
+ */
+async fn admin(path: &str, command: &str) -> String {
+    let mut stream = UnixStream::connect(path).await.expect("Admin channel is open");
+    stream.write_all(format!("{}\n", command).as_bytes()).await.unwrap();
+
+    let mut lines = BufReader::new(stream).lines();
+    let mut out = String::new();
+    while let Some(line) = lines.next_line().await.unwrap() {
+        match line.as_str() {
+            "ok" => return out,
+            _ if line.starts_with("error") => panic!("{} failed ({})", command, line),
+            _ => out += &line,
+        }
+    }
+    out
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn server_messages_round_trip() {
+    let dir = env::temp_dir().join(format!("flow-test-{}", std::process::id()));
+    let admin_path = dir.join("admin.sock").to_string_lossy().to_string();
+    std::fs::create_dir_all(&dir).unwrap();
+    env::set_var("FLOW_DATA", &dir);
+    env::set_var("FLOW_ADMIN", &admin_path);
+    env::set_var("FLOW_METRICS", "127.0.0.1:0");
+
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    tokio::spawn(Server::builder().addr(format!("127.0.0.1:{}", port)).build().run());
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let url = format!("ws://127.0.0.1:{}", port);
+
+    // Bots are registered without the slow password hashing:
+    let alpha_token = admin(&admin_path, "bot alpha").await;
+    let beta_token = admin(&admin_path, "bot beta").await;
+
+    let mut a = Conn::connect(&url).await;
+    a.send(json!({ "type": "login", "api_token": alpha_token })).await;
+    let alpha = a.expect("welcome").await["user"]["id"].as_str().unwrap().to_string();
+    a.expect("login").await;
+
+    let mut b = Conn::connect(&url).await;
+    b.send(json!({ "type": "login", "api_token": beta_token })).await;
+    let beta = b.expect("welcome").await["user"]["id"].as_str().unwrap().to_string();
+    let token = b.expect("login").await["token"].as_str().unwrap().to_string();
+    a.expect("join").await;
+
+    // Chat, replies, actions and direct messages:
+    a.send(json!({ "type": "chat", "content": "hello \"there\"\nworld", "nonce": "1" })).await;
+    let id = a.expect("ack").await["id"].as_u64().unwrap();
+    assert_eq!(b.expect("chat").await["content"], "hello \"there\"\nworld");
+
+    a.send(json!({ "type": "chat", "content": "reply", "parent": id })).await;
+    b.expect("chat").await;
+    a.send(json!({ "type": "chat", "content": "/me waves" })).await;
+    assert_eq!(b.expect("chat").await["action"], true);
+
+    a.send(json!({ "type": "chat", "content": "psst", "target": beta, "nonce": "2" })).await;
+    let direct = a.expect("ack").await["id"].as_u64().unwrap();
+    b.expect("chat").await;
+    b.send(json!({ "type": "read", "id": direct })).await;
+    a.expect("read").await;
+
+    // Changes to a message:
+    a.send(json!({ "type": "edit", "id": id, "content": "edited", "nonce": "3" })).await;
+    a.expect("ack").await;
+    b.expect("edit").await;
+    a.send(json!({ "type": "react", "id": id, "emoji": "👍", "remove": false })).await;
+    b.expect("react").await;
+    a.send(json!({ "type": "history", "limit": 50 })).await;
+    a.expect("history").await;
+    a.send(json!({ "type": "delete", "id": id })).await;
+    b.expect("delete").await;
+
+    // Presence, typing and files:
+    a.send(json!({ "type": "presence", "status": "away", "text": "lunch" })).await;
+    b.expect("presence").await;
+    a.send(json!({ "type": "typing", "typing": true })).await;
+    b.expect("typing").await;
+    a.send(json!({ "type": "file", "name": "hello.txt", "content": "aGVsbG8=" })).await;
+    b.expect("file").await;
+
+    // Profiles:
+    a.send(json!({ "type": "file", "name": "me.png", "content": "aGVsbG8=", "avatar": true })).await;
+    b.expect("profile").await;
+    a.send(json!({ "type": "profile", "update": { "bio": "beep", "timezone": "UTC" } })).await;
+    b.expect("profile").await;
+    b.send(json!({ "type": "profile", "id": alpha })).await;
+    assert_eq!(b.expect("profile").await["profile"]["avatar"]["content"], "aGVsbG8=");
+
+    // Peer to peer:
+    a.send(json!({ "type": "request", "target": beta })).await;
+    let offer = b.expect("offer").await["id"].as_str().unwrap().to_string();
+    b.send(json!({ "type": "offer", "accept": true, "id": offer })).await;
+    a.expect("confirm").await;
+    b.expect("confirm").await;
+    a.send(json!({ "type": "session", "offer": offer, "port": 4000 })).await;
+    b.expect("peer").await;
+
+    // Commands, roles and the topic:
+    a.send(json!({ "type": "chat", "content": "/help" })).await;
+    a.expect("command").await;
+    admin(&admin_path, &format!("role {} moderator", alpha)).await;
+    a.expect("role").await;
+    a.send(json!({ "type": "chat", "content": "/topic Testing" })).await;
+    b.expect("topic").await;
+    admin(&admin_path, "broadcast Hi all").await;
+    b.expect("announcement").await;
+
+    // Renames and blocks:
+    a.send(json!({ "type": "rename", "name": "alpha two" })).await;
+    b.expect("rename").await;
+    b.send(json!({ "type": "block", "target": alpha, "remove": false, "chat": true })).await;
+    b.expect("block").await;
+
+    // Errors:
+    a.send(json!({ "type": "nonsense" })).await;
+    a.expect("error").await;
+
+    // Dropping the connection without closing it leaves time to resume:
+    drop(b);
+    a.expect("reconnecting").await;
+    let mut b = Conn::connect(&url).await;
+    b.send(json!({ "type": "resume", "token": token })).await;
+    b.expect("resume").await;
+    a.expect("resumed").await;
+
+    b.stream.close(None).await.unwrap();
+    a.expect("leave").await;
+
+    std::fs::remove_dir_all(&dir).ok();
+}